@@ -1,53 +1,106 @@
-use std::collections::{HashMap, HashSet};
-use std::hash::{Hash, Hasher};
+use std::cmp::Reverse;
+use std::env;
+use std::fmt;
 use std::str::FromStr;
 use std::vec::Vec;
+use itertools::Itertools;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
 use ya_advent_lib::read::read_input;
 
-#[derive(Copy,Clone,Eq,PartialEq,Hash)]
-enum Seg {
-    A, B, C, D, E, F, G,
+// 7-segment glyphs use the puzzle's lettering (a top, b/c upper left/right,
+// d middle, e/f lower left/right, g bottom). 14-segment glyphs use the usual
+// A-F order around the outside starting at the top, then g/h for the halves
+// of the middle bar and i-k/l-n for the upper and lower diagonal/centre/diagonal.
+const DIGITS7: &[(char, &str)] = &[
+    ('0', "abcefg"), ('1', "cf"), ('2', "acdeg"), ('3', "acdfg"), ('4', "bcdf"),
+    ('5', "abdfg"), ('6', "abdefg"), ('7', "acf"), ('8', "abcdefg"), ('9', "abcdfg"),
+];
+const HEX7: &[(char, &str)] = &[
+    ('A', "abcdef"), ('b', "bdefg"), ('C', "abeg"), ('d', "cdefg"), ('E', "abdeg"), ('F', "abde"),
+];
+const ALNUM14: &[(char, &str)] = &[
+    ('0', "abcdefkl"), ('1', "bck"), ('2', "abdegh"), ('3', "abcdh"), ('4', "bcfgh"),
+    ('5', "adfgn"), ('6', "acdefgh"), ('7', "abc"), ('8', "abcdefgh"), ('9', "abcdfgh"),
+    ('A', "abcefgh"), ('B', "abcdhjm"), ('C', "adef"), ('D', "abcdjm"), ('E', "adefg"),
+    ('F', "aefg"), ('G', "acdefh"), ('H', "bcefgh"), ('I', "adjm"), ('J', "bcde"),
+    ('K', "efgkn"), ('L', "def"), ('M', "bcefik"), ('N', "bcefin"), ('O', "abcdef"),
+    ('P', "abefgh"), ('Q', "abcdefn"), ('R', "abefghn"), ('S', "acdfgh"), ('T', "ajm"),
+    ('U', "bcdef"), ('V', "efkl"), ('W', "bcefln"), ('X', "ikln"), ('Y', "ikm"),
+    ('Z', "adkl"),
+];
+
+fn seg_char(s: usize) -> char {
+    (b'a' + s as u8) as char
 }
 
-#[derive(Clone,Eq,PartialEq)]
-struct SSeg {
-    segs: HashSet<Seg>,
+#[derive(Clone,Debug)]
+struct GlyphTable {
+    nsegs: usize,
+    glyphs: Vec<(char, SSeg)>,
 }
 
-impl Hash for SSeg {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.as_string().hash(state);
+impl GlyphTable {
+    fn new(nsegs: usize, glyphs: &[(char, &str)]) -> Self {
+        let glyphs: Vec<(char, SSeg)> = glyphs
+            .iter()
+            .map(|(c, s)| (*c, s.parse().unwrap()))
+            .collect();
+        assert!(nsegs <= 32 && glyphs.iter().all(|(_, s)| s.0 >> nsegs == 0));
+        assert!(glyphs.iter().map(|(_, s)| s).all_unique());
+        Self { nsegs, glyphs }
+    }
+    fn digits() -> Self {
+        Self::new(7, DIGITS7)
+    }
+    fn hex() -> Self {
+        Self::new(7, &[DIGITS7, HEX7].concat())
+    }
+    fn alnum14() -> Self {
+        Self::new(14, ALNUM14)
+    }
+    fn symbol(&self, s: &SSeg) -> Option<char> {
+        self.glyphs.iter().find(|(_, g)| g == s).map(|(c, _)| *c)
+    }
+    /// The symbol for a pattern whose segment count is shared by no other glyph.
+    fn by_len(&self, s: &SSeg) -> Option<char> {
+        self.glyphs
+            .iter()
+            .filter(|(_, g)| g.len() == s.len())
+            .exactly_one()
+            .ok()
+            .map(|(c, _)| *c)
     }
 }
 
+#[derive(Copy,Clone,Debug,Eq,PartialEq,Hash)]
+struct SSeg(u32);
+
 impl SSeg {
-    fn new(chars: &str) -> Self {
-        Self {
-          segs: chars.chars().map(|c| match c {
-                'a'=>Seg::A, 'b'=>Seg::B, 'c'=>Seg::C, 'd'=>Seg::D,
-                'e'=>Seg::E, 'f'=>Seg::F, 'g'=>Seg::G, _=>panic!(),
-            }).collect(),
-        }
+    fn len(&self) -> u32 {
+        self.0.count_ones()
     }
-    fn val_by_len(&self) -> Option<u8> {
-        match self.segs.len() {
-            2 => Some(1),
-            3 => Some(7),
-            4 => Some(4),
-            7 => Some(8),
-            _ => None,
-        }
+    fn wires(&self) -> impl Iterator<Item=usize> + '_ {
+        (0..32).filter(|w| self.0 & (1 << w) != 0)
     }
-    fn as_string(&self) -> String {
-        let mut chars: Vec<char> = self.segs
-            .iter()
-            .map(|s| match s {
-                Seg::A=>'a', Seg::B=>'b', Seg::C=>'c', Seg::D=>'d',
-                Seg::E=>'e', Seg::F=>'f', Seg::G=>'g',
+}
+
+impl FromStr for SSeg {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.chars()
+            .try_fold(0, |acc, c| match c {
+                'a'..='z' => Ok(acc | 1 << (c as u8 - b'a')),
+                _ => Err(()),
             })
-            .collect();
-        chars.sort();
-        chars.iter().collect()
+            .map(SSeg)
+    }
+}
+
+impl fmt::Display for SSeg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.wires().map(seg_char).collect::<String>())
     }
 }
 
@@ -59,9 +112,9 @@ struct Disp {
 impl FromStr for Disp {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut itr1 = s.split(" | ");
-        let patterns = itr1.next().unwrap().split(' ').map(SSeg::new).collect();
-        let displays = itr1.next().unwrap().split(' ').map(SSeg::new).collect();
+        let (pat, disp) = s.split_once(" | ").ok_or(())?;
+        let patterns = pat.split(' ').map(|p| p.parse()).collect::<Result<_,_>>()?;
+        let displays = disp.split(' ').map(|p| p.parse()).collect::<Result<_,_>>()?;
         Ok (
             Disp {
                 patterns,
@@ -71,116 +124,276 @@ impl FromStr for Disp {
     }
 }
 
-impl Disp {
-    fn solve(&self) -> usize {
-        let mut digmap: HashMap<u8, SSeg> =
-            self.patterns
-                .iter()
-                .map(|p| (p, p.val_by_len()))
-                .filter(|(_, v)| v.is_some())
-                .map(|(p, v)| (v.unwrap(), p.clone()))
-                .collect();
-        // digmap now has 1, 4, 7, and 8
+#[derive(Debug,Eq,PartialEq)]
+enum SolveError {
+    NoMapping,
+    /// More than one wiring or reading fits.
+    Ambiguous,
+}
 
-        let mut unknowns: HashSet<SSeg> =
-            self.patterns
-                .iter()
-                .map(|p| (p, p.val_by_len()))
-                .filter(|(_, v)| v.is_none())
-                .map(|(p, _)| p.clone())
-                .collect();
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolveError::NoMapping => write!(f, "no valid mapping"),
+            SolveError::Ambiguous => write!(f, "ambiguous: several mappings fit"),
+        }
+    }
+}
 
-        // find the 9 which is the only number besides 8 that has the segments
-        // from 4|7
-        let abcdf:HashSet<Seg> = digmap[&4].segs
-            .union(&digmap[&7].segs)
-            .cloned().collect();
-
-        let nine = unknowns
-            .iter().find(|p| p.segs.is_superset(&abcdf))
-            .unwrap()
-            .clone();
-
-        digmap.insert(9, nine.clone());
-        unknowns.remove(&nine);
-        let gseg = *nine.segs.difference(&abcdf).next().unwrap();
-
-        // the only digits which have ACFG (that isn't 8 or 9) are 0 and 3
-        let mut acfg = digmap[&7].segs.clone();
-        acfg.insert(gseg);
-        let zero_or_three: Vec<SSeg> = unknowns
+/// `Wiring.0[w]` is the segment actually driven by wire `w`.
+#[derive(Clone,Debug,Eq,PartialEq)]
+struct Wiring(Vec<usize>);
+
+impl Wiring {
+    fn random<R: Rng>(nsegs: usize, rng: &mut R) -> Self {
+        let mut w: Vec<usize> = (0..nsegs).collect();
+        w.shuffle(rng);
+        Wiring(w)
+    }
+    fn inverse(&self) -> Self {
+        let mut inv = vec![0; self.0.len()];
+        self.0.iter().enumerate().for_each(|(w, s)| inv[*s] = w);
+        Wiring(inv)
+    }
+    fn map(&self, s: &SSeg) -> SSeg {
+        SSeg(s.wires().fold(0, |acc, w| acc | 1 << self.0[w]))
+    }
+    fn miswired(&self) -> Vec<(char, char)> {
+        self.0
             .iter()
-            .filter(|p| p.segs.is_superset(&acfg))
-            .cloned()
-            .collect();
-        assert_eq!(zero_or_three.len(), 2);
-        if zero_or_three[0].segs.len() == 6 {
-            digmap.insert(0, zero_or_three[0].clone());
-            digmap.insert(3, zero_or_three[1].clone());
-        } else {
-            digmap.insert(0, zero_or_three[1].clone());
-            digmap.insert(3, zero_or_three[0].clone());
-        }
-        unknowns.remove(&zero_or_three[0]);
-        unknowns.remove(&zero_or_three[1]);
+            .enumerate()
+            .filter(|(w, s)| w != *s)
+            .map(|(w, s)| (seg_char(w), seg_char(*s)))
+            .collect()
+    }
+}
 
-        let be_segs:HashSet<Seg> = digmap[&0].segs.difference(&acfg).cloned().collect();   // B and E segs
-        let eseg = *be_segs.difference(&digmap[&4].segs).next().unwrap();
+impl fmt::Display for Wiring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = self.miswired()
+            .iter()
+            .map(|(w, s)| format!("{}->{}", w, s))
+            .join(" ");
+        write!(f, "{}", if s.is_empty() { "correct" } else { &s })
+    }
+}
 
-        // 6 is the only remaining unknown that has B and E
-        let six = unknowns
-            .iter().find(|p| p.segs.is_superset(&be_segs))
-            .unwrap()
-            .clone();
+struct Search<'a, F: FnMut(Wiring) -> bool> {
+    table: &'a GlyphTable,
+    patterns: Vec<SSeg>,
+    /// Wires in the order they're assigned: those lit in the most patterns
+    /// first, so that wires nothing constrains are left to the end.
+    order: Vec<usize>,
+    /// How many wires are lit in some pattern.
+    lit: usize,
+    assign: Vec<usize>,
+    /// Called with each consistent wiring; returns whether to keep looking.
+    visit: F,
+}
 
-        digmap.insert(6, six.clone());
-        unknowns.remove(&six);
+impl<F: FnMut(Wiring) -> bool> Search<'_, F> {
+    fn consistent(&self, n_assigned: usize) -> bool {
+        self.patterns.iter().all(|p| {
+            let (known_in, known_out) = self.order[..n_assigned]
+                .iter()
+                .fold((0, 0), |(i, o), &w| {
+                    let bit = 1 << self.assign[w];
+                    if p.0 & (1 << w) != 0 { (i | bit, o) } else { (i, o | bit) }
+                });
+            self.table.glyphs
+                .iter()
+                .any(|(_, g)| g.len() == p.len() && g.0 & known_in == known_in && g.0 & known_out == 0)
+        })
+    }
+    /// Returns false once `visit` has asked to stop.
+    fn run(&mut self, n: usize, used: u32) -> bool {
+        if n == self.lit {
+            // the wires left are lit in no pattern, so every way of placing
+            // them fits and reads the same: try two at most
+            let free: Vec<usize> = (0..self.table.nsegs).filter(|s| used & (1 << s) == 0).collect();
+            let mut wiring = self.assign.clone();
+            for (w, s) in self.order[n..].iter().zip(free.iter()) {
+                wiring[*w] = *s;
+            }
+            if !(self.visit)(Wiring(wiring.clone())) {
+                return false;
+            }
+            if free.len() >= 2 {
+                wiring.swap(self.order[n], self.order[n + 1]);
+                return (self.visit)(Wiring(wiring));
+            }
+            return true;
+        }
+        for s in 0..self.table.nsegs {
+            if used & (1 << s) == 0 {
+                self.assign[self.order[n]] = s;
+                if self.consistent(n + 1) && !self.run(n + 1, used | 1 << s) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
 
-        // 2 is the only remaining unknown to have E
-        let two = unknowns
-            .iter().find(|p| p.segs.contains(&eseg))
-            .unwrap()
-            .clone();
-        digmap.insert(2, two.clone());
-        unknowns.remove(&two);
+impl Disp {
+    /// Produces an input line showing `text` on a display wired as `wiring`:
+    /// every glyph of the table in random order, then the output patterns.
+    /// Letter order within each pattern is shuffled too.
+    fn encode<R: Rng>(table: &GlyphTable, text: &str, wiring: &Wiring, rng: &mut R) -> Option<String> {
+        let scramble = wiring.inverse();
+        let mut glyphs: Vec<&SSeg> = table.glyphs.iter().map(|(_, g)| g).collect();
+        glyphs.shuffle(rng);
+        let mut pattern = |g: &SSeg| -> String {
+            let mut chars: Vec<char> = scramble.map(g).to_string().chars().collect();
+            chars.shuffle(rng);
+            chars.into_iter().collect()
+        };
+        let outputs: Vec<String> = text
+            .chars()
+            .map(|c| table.glyphs.iter().find(|(s, _)| *s == c).map(|(_, g)| pattern(g)))
+            .collect::<Option<_>>()?;
+        let patterns: Vec<String> = glyphs.into_iter().map(pattern).collect();
+        Some(format!("{} | {}", patterns.join(" "), outputs.join(" ")))
+    }
 
-        // should be one left, and thats 5
-        assert_eq!(unknowns.len(), 1);
-        let five = unknowns.iter().next().unwrap().clone();
-        digmap.insert(5, five);
+    /// Passes each consistent wiring to `visit` until it returns false.
+    fn wirings(&self, table: &GlyphTable, visit: impl FnMut(Wiring) -> bool) {
+        let patterns: Vec<SSeg> = self.patterns.iter().chain(self.displays.iter()).copied().unique().collect();
+        if patterns.iter().any(|p| p.0 >> table.nsegs != 0) {
+            return;
+        }
+        let uses = |w: &usize| patterns.iter().filter(|p| p.0 & (1 << w) != 0).count();
+        let order: Vec<usize> = (0..table.nsegs).sorted_by_key(|w| Reverse(uses(w))).collect();
+        let lit = order.iter().filter(|w| uses(w) > 0).count();
+        let mut search = Search { table, patterns, order, lit, assign: vec![0; table.nsegs], visit };
+        search.run(0, 0);
+    }
 
-        let rdigmap: HashMap<SSeg,u8> = digmap.iter().map(|(k,v)| (v.clone(), *k)).collect();
+    fn solve(&self, table: &GlyphTable) -> Result<Wiring, SolveError> {
+        // a second wiring is all it takes to know the answer is ambiguous
+        let mut found = Vec::new();
+        self.wirings(table, |w| {
+            found.push(w);
+            found.len() < 2
+        });
+        match found.len() {
+            0 => Err(SolveError::NoMapping),
+            1 => Ok(found.pop().unwrap()),
+            _ => Err(SolveError::Ambiguous),
+        }
+    }
 
-        self.displays
-            .iter()
-            .map(|d| rdigmap[d] as usize)
-            .fold(0usize, |acc, v| acc * 10 + v)
+    /// Succeeds whenever every consistent wiring reads the output the same way,
+    /// even if the wiring itself can't be pinned down.
+    fn decode(&self, table: &GlyphTable) -> Result<String, SolveError> {
+        let mut readings: Vec<String> = Vec::new();
+        self.wirings(table, |wiring| {
+            let reading: String = self.displays
+                .iter()
+                .map(|d| table.symbol(&wiring.map(d)).unwrap())
+                .collect();
+            if !readings.contains(&reading) {
+                readings.push(reading);
+            }
+            readings.len() < 2
+        });
+        match readings.len() {
+            0 => Err(SolveError::NoMapping),
+            1 => Ok(readings.pop().unwrap()),
+            _ => Err(SolveError::Ambiguous),
+        }
     }
 }
 
+/// Random `(text, line)` pairs for regression testing; the same seed always
+/// yields the same corpus.
+fn corpus(table: &GlyphTable, rows: usize, len: usize, seed: u64) -> Vec<(String, String)> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..rows)
+        .map(|_| {
+            let text: String = (0..len)
+                .map(|_| table.glyphs[rng.gen_range(0..table.glyphs.len())].0)
+                .collect();
+            let wiring = Wiring::random(table.nsegs, &mut rng);
+            let line = Disp::encode(table, &text, &wiring, &mut rng).unwrap();
+            (text, line)
+        })
+        .collect()
+}
+
 fn part1(input: &[Disp]) -> usize {
+    let table = GlyphTable::digits();
     input
         .iter()
         .map(|row| row.displays
             .iter()
-            .filter(|s| s.val_by_len().is_some())
+            .filter(|s| table.by_len(s).is_some())
             .count()
         )
         .sum()
 }
 
-fn part2(input: &[Disp]) -> usize {
-    let list: Vec<usize> = input
+/// The sum of every row's reading, or the first row (counting from 0) that
+/// can't be read.
+fn part2(input: &[Disp]) -> Result<usize, (usize, SolveError)> {
+    let table = GlyphTable::digits();
+    input
         .iter()
-        .map(|row| row.solve())
-        .collect();
-    list.iter().sum()
+        .enumerate()
+        .map(|(n, row)| row.decode(&table).map(|s| s.parse::<usize>().unwrap()).map_err(|e| (n, e)))
+        .sum()
+}
+
+/// The glyph table named by `DAY08_GLYPHS`, if set.
+fn glyphs_from_env() -> Option<GlyphTable> {
+    let name = env::var("DAY08_GLYPHS").ok()?;
+    match name.as_str() {
+        "digits" => Some(GlyphTable::digits()),
+        "hex" => Some(GlyphTable::hex()),
+        "alnum14" => Some(GlyphTable::alnum14()),
+        _ => {
+            eprintln!("DAY08_GLYPHS must be digits, hex or alnum14");
+            std::process::exit(1);
+        },
+    }
 }
 
 fn main() {
+    // DAY08_CORPUS=<seed> prints ten scrambled four-glyph rows instead, drawn
+    // from the DAY08_GLYPHS table
+    if let Ok(seed) = env::var("DAY08_CORPUS") {
+        let Ok(seed) = seed.parse() else {
+            eprintln!("DAY08_CORPUS must be a number");
+            std::process::exit(1);
+        };
+        let table = glyphs_from_env().unwrap_or_else(GlyphTable::digits);
+        for (_, line) in corpus(&table, 10, 4, seed) {
+            println!("{}", line);
+        }
+        return;
+    }
     let input: Vec<Disp> = read_input();
     println!("Part 1: {}", part1(&input));
-    println!("Part 2: {}", part2(&input));
+    match part2(&input) {
+        Ok(sum) => println!("Part 2: {}", sum),
+        Err((n, e)) => {
+            eprintln!("row {}: {}", n + 1, e);
+            std::process::exit(1);
+        },
+    }
+
+    // DAY08_GLYPHS=digits|hex|alnum14 also reads every row with that table,
+    // showing the miswiring too where only one fits
+    if let Some(table) = glyphs_from_env() {
+        for (n, row) in input.iter().enumerate() {
+            match (row.decode(&table), row.solve(&table)) {
+                (Ok(text), Ok(wiring)) => println!("{} ({})", text, wiring),
+                (Ok(text), Err(_)) => println!("{}", text),
+                (Err(e), _) => println!("row {}: {}", n + 1, e),
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -192,9 +405,83 @@ mod tests {
     fn day08_test() {
         let input: Vec<Disp> = test_input(include_str!("day08.testinput"));
         assert_eq!(part1(&input), 26);
-        assert_eq!(part2(&input), 61229);
+        assert_eq!(part2(&input), Ok(61229));
         let input2: Vec<Disp> = test_input("acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf");
         assert_eq!(part1(&input2), 0);
-        assert_eq!(part2(&input2), 5353);
+        assert_eq!(part2(&input2), Ok(5353));
+    }
+
+    #[test]
+    fn day08_wiring_test() {
+        let table = GlyphTable::digits();
+        let row: Disp = "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf".parse().unwrap();
+        let wiring = row.solve(&table).unwrap();
+        assert_eq!(wiring.miswired().len(), 7);
+        assert_eq!(wiring.to_string(), "a->c b->f c->g d->a e->b f->d g->e");
+        let ok: Disp = "abcefg cf acdeg acdfg bcdf abdfg abdefg acf abcdefg abcdfg | cf cf cf cf".parse().unwrap();
+        assert_eq!(ok.solve(&table).unwrap().to_string(), "correct");
+        assert_eq!(ok.decode(&table), Ok("1111".into()));
+        let bad: Disp = "ab abc abcd abcde abcdef abcdefg ac ad ae af | ab ab ab ab".parse().unwrap();
+        assert_eq!(bad.solve(&table), Err(SolveError::NoMapping));
+        let partial: Disp = "ab | ab".parse().unwrap();
+        assert_eq!(partial.solve(&table), Err(SolveError::Ambiguous));
+        assert_eq!(partial.decode(&table), Ok("1".into()));
+
+        // part 2 goes by the reading, so an ambiguous wiring still counts
+        let rows: Vec<Disp> = test_input("ab | ab\nabcefg cf acdeg acdfg bcdf abdfg abdefg acf abcdefg abcdfg | cf cf cf cf");
+        assert_eq!(part2(&rows), Ok(1 + 1111));
+        let rows: Vec<Disp> = test_input("ab | ab\nab abc abcd abcde abcdef abcdefg ac ad ae af | ab ab ab ab");
+        assert_eq!(part2(&rows), Err((1, SolveError::NoMapping)));
+    }
+
+    #[test]
+    fn day08_glyph_table_test() {
+        let hex = GlyphTable::hex();
+        let scramble = Wiring(vec![3, 4, 0, 5, 6, 1, 2]);
+        let row = |out: &str| -> Disp {
+            let pats = hex.glyphs.iter().map(|(_, g)| scramble.map(g).to_string()).join(" ");
+            let outs = out.chars().map(|c| {
+                let g = hex.glyphs.iter().find(|(s, _)| *s == c).unwrap().1;
+                scramble.map(&g).to_string()
+            }).join(" ");
+            format!("{} | {}", pats, outs).parse().unwrap()
+        };
+        assert_eq!(row("C0dE").decode(&hex), Ok("C0dE".into()));
+        assert_eq!(row("bEEF").solve(&hex).unwrap().to_string(), "a->c b->f c->g d->a e->b f->d g->e");
+
+        let a14 = GlyphTable::alnum14();
+        let pats = a14.glyphs.iter().map(|(_, g)| g.to_string()).join(" ");
+        let row: Disp = format!("{} | adfgn abcefgh adjm adefg", pats).parse().unwrap();
+        assert_eq!(row.decode(&a14), Ok("5AIE".into()));
+        assert_eq!(row.solve(&a14).unwrap().to_string(), "correct");
+        // millions of wirings fit a sparse row; the search stops at the second
+        let sparse: Disp = "bck abc | bck".parse().unwrap();
+        assert_eq!(sparse.decode(&a14), Err(SolveError::Ambiguous));
+        assert_eq!(sparse.solve(&a14), Err(SolveError::Ambiguous));
+    }
+
+    #[test]
+    fn day08_encode_test() {
+        let table = GlyphTable::digits();
+        let mut rng = StdRng::seed_from_u64(8);
+        let wiring = Wiring::random(7, &mut rng);
+        let line = Disp::encode(&table, "4096", &wiring, &mut rng).unwrap();
+        let row: Disp = line.parse().unwrap();
+        assert_eq!(row.patterns.len(), 10);
+        assert!(row.patterns.iter().all_unique());
+        assert_eq!(row.displays.len(), 4);
+        assert_eq!(row.solve(&table), Ok(wiring));
+        assert_eq!(row.decode(&table), Ok("4096".into()));
+        assert_eq!(Disp::encode(&table, "40x6", &Wiring::random(7, &mut rng), &mut rng), None);
+
+        let rows = corpus(&table, 200, 4, 2021);
+        assert_eq!(rows, corpus(&table, 200, 4, 2021));
+        for (text, line) in rows {
+            assert_eq!(line.parse::<Disp>().unwrap().decode(&table), Ok(text));
+        }
+        let hex = GlyphTable::hex();
+        for (text, line) in corpus(&hex, 20, 6, 16) {
+            assert_eq!(line.parse::<Disp>().unwrap().decode(&hex), Ok(text));
+        }
     }
 }
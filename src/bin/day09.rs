@@ -1,5 +1,8 @@
 use std::cmp::Reverse;
-use std::collections::{HashSet, VecDeque};
+use std::collections::VecDeque;
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
 use std::vec::Vec;
 use itertools::Itertools;
 use ya_advent_lib::coords::Coord2D;
@@ -7,57 +10,178 @@ use ya_advent_lib::grid::Grid;
 use ya_advent_lib::read::read_input;
 
 fn mkgrid(input: &[String]) -> Grid<i32> {
-    Grid::from_input_map(input, 9, 1, |c| match c {
+    Grid::from_input_map(input, 9, 0, |c| match c {
         '0'..='9' => (c as u8 - b'0') as i32,
         _ => panic!(),
     })
 }
 
-fn find_low_points(grid: &Grid<i32>) -> Vec<Coord2D> {
-    let mut points = Vec::new();
+/// How cells connect and which heights block flow between basins.
+#[derive(Clone,Copy)]
+struct Terrain {
+    diagonal: bool,
+    wall: i32,
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Terrain { diagonal: false, wall: 9 }
+    }
+}
+
+impl Terrain {
+    fn adjacent(&self, c: Coord2D) -> Vec<Coord2D> {
+        if self.diagonal { c.neighbors8() } else { c.neighbors4() }
+    }
+    fn neighbors(&self, grid: &Grid<i32>, c: Coord2D) -> Vec<Coord2D> {
+        self.adjacent(c).into_iter().filter(|n| grid.contains_coord(*n)).collect()
+    }
+    fn is_wall(&self, height: i32) -> bool {
+        height >= self.wall
+    }
+}
+
+/// A connected region of equal height whose neighbors are all higher. On a
+/// grid with no flat spots every region is a single cell.
+#[derive(Debug,Eq,PartialEq)]
+struct LowRegion {
+    height: i32,
+    cells: Vec<Coord2D>,
+}
+
+fn find_low_points(grid: &Grid<i32>, terrain: &Terrain) -> Vec<LowRegion> {
+    let mut seen = Grid::new(0, 0, grid.x_bounds().end - 1, grid.y_bounds().end - 1, false);
+    let mut regions = Vec::new();
+    grid.for_each(|height, x, y| {
+        if seen.get(x, y) || terrain.is_wall(height) {
+            return;
+        }
+        let mut cells = vec![Coord2D::new(x, y)];
+        let mut is_low = true;
+        seen.set(x, y, true);
+        let mut i = 0;
+        while i < cells.len() {
+            for n in terrain.neighbors(grid, cells[i]) {
+                let nh = grid.get_c(n);
+                if nh < height {
+                    is_low = false;
+                }
+                else if nh == height && !seen.get_c(n) {
+                    seen.set_c(n, true);
+                    cells.push(n);
+                }
+            }
+            i += 1;
+        }
+        if is_low {
+            regions.push(LowRegion { height, cells });
+        }
+    });
+    regions
+}
+
+struct Basin {
+    size: usize,
+    low: Coord2D,
+    /// Cells of the basin that touch a cell outside it.
+    boundary: Vec<Coord2D>,
+}
+
+struct Basins {
+    labels: Grid<Option<usize>>,
+    basins: Vec<Basin>,
+}
+
+/// Labels every basin with a single flood fill over the grid.
+fn label_basins(grid: &Grid<i32>, terrain: &Terrain) -> Basins {
     let xb = grid.x_bounds();
     let yb = grid.y_bounds();
-    for y in (yb.start + 1)..(yb.end - 1) {
-        for x in (xb.start + 1)..(xb.end - 1) {
-            let v = grid.get(x, y);
-            if Coord2D::new(x, y)
-                .neighbors4()
-                .into_iter()
-                .all(|n| v < grid.get_c(n)) {
-                    points.push(Coord2D::new(x, y));
+    let mut labels = Grid::new(xb.start, yb.start, xb.end - 1, yb.end - 1, None);
+    let mut basins: Vec<Basin> = Vec::new();
+    let mut queue: VecDeque<Coord2D> = VecDeque::new();
+    grid.for_each(|v, x, y| {
+        if terrain.is_wall(v) || labels.get(x, y).is_some() {
+            return;
+        }
+        let label = basins.len();
+        let mut basin = Basin { size: 0, low: Coord2D::new(x, y), boundary: Vec::new() };
+        labels.set(x, y, Some(label));
+        queue.push_back(Coord2D::new(x, y));
+        while let Some(c) = queue.pop_front() {
+            basin.size += 1;
+            if grid.get_c(c) < grid.get_c(basin.low) {
+                basin.low = c;
+            }
+            let mut edge = false;
+            for n in terrain.adjacent(c) {
+                if !grid.contains_coord(n) || terrain.is_wall(grid.get_c(n)) {
+                    edge = true;
+                }
+                else if labels.get_c(n).is_none() {
+                    labels.set_c(n, Some(label));
+                    queue.push_back(n);
+                }
+            }
+            if edge {
+                basin.boundary.push(c);
             }
         }
-    }
-    points
+        basins.push(basin);
+    });
+    Basins { labels, basins }
 }
 
-fn find_basin_size(grid: &Grid<i32>, start: Coord2D) -> i32 {
-    let mut queue: VecDeque<Coord2D> = VecDeque::new();
-    let mut visited: HashSet<Coord2D> = HashSet::new();
-    queue.push_back(start);
-    let mut count = 0;
-    while let Some(c) = queue.pop_front() {
-        if grid.get_c(c) < 9 && !visited.contains(&c) {
-            count += 1;
-            visited.insert(c);
-            c.neighbors4().into_iter().for_each(|n| queue.push_back(n));
+impl Basins {
+    /// Writes the basin map as a binary PPM image, one pixel per cell. Each
+    /// basin gets its own hue, shaded by height; walls are black.
+    fn write_ppm(&self, grid: &Grid<i32>, out: &mut dyn Write) -> io::Result<()> {
+        let xb = grid.x_bounds_orig();
+        let yb = grid.y_bounds_orig();
+        write!(out, "P6\n{} {}\n255\n", xb.end - xb.start, yb.end - yb.start)?;
+        let mut pixels = Vec::new();
+        for y in yb {
+            for x in xb.clone() {
+                let rgb = match self.labels.get(x, y) {
+                    None => [0, 0, 0],
+                    Some(label) => {
+                        let shade = 255 - 18 * grid.get(x, y).clamp(0, 9) as u32;
+                        basin_color(label).map(|c| (c as u32 * shade / 255) as u8)
+                    },
+                };
+                pixels.extend_from_slice(&rgb);
+            }
         }
+        out.write_all(&pixels)
     }
-    count
+}
+
+fn basin_color(label: usize) -> [u8; 3] {
+    // spread successive labels around the hue wheel by the golden angle
+    let h = (label as f64 * 137.508) % 360.0 / 60.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    [r, g, b].map(|c: f64| (c * 255.0) as u8)
 }
 
 fn part1(grid: &Grid<i32>) -> i32 {
-    find_low_points(grid)
+    find_low_points(grid, &Terrain::default())
         .into_iter()
-        .map(|c| grid.get_c(c) + 1)
+        .map(|r| r.height + 1)
         .sum()
 }
 
-fn part2(grid: &Grid<i32>) -> i32 {
-    find_low_points(grid)
-        .into_iter()
-        .map(|c| find_basin_size(grid, c))
-        .map(Reverse)
+fn part2(grid: &Grid<i32>) -> usize {
+    label_basins(grid, &Terrain::default())
+        .basins
+        .iter()
+        .map(|b| Reverse(b.size))
         .k_smallest(3)
         .map(|s| s.0)
         .product()
@@ -69,6 +193,15 @@ fn main() {
 
     println!("Part 1: {}", part1(&grid));
     println!("Part 2: {}", part2(&grid));
+
+    // DAY09_PPM=<file> also writes the basin map as an image
+    if let Ok(path) = env::var("DAY09_PPM") {
+        let basins = label_basins(&grid, &Terrain::default());
+        if let Err(e) = File::create(&path).and_then(|mut f| basins.write_ppm(&grid, &mut f)) {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -83,4 +216,52 @@ mod tests {
         assert_eq!(part1(&grid), 15);
         assert_eq!(part2(&grid), 1134);
     }
+
+    #[test]
+    fn day09_basins_test() {
+        let input: Vec<String> = test_input(include_str!("day09.testinput"));
+        let grid = mkgrid(&input);
+        let basins = label_basins(&grid, &Terrain::default());
+        assert_eq!(basins.basins.len(), 4);
+        let mut lows: Vec<Coord2D> = basins.basins.iter().map(|b| b.low).collect();
+        lows.sort_by_key(|c| (c.y, c.x));
+        let regions: Vec<Coord2D> = find_low_points(&grid, &Terrain::default())
+            .into_iter()
+            .map(|r| r.cells[0])
+            .collect();
+        assert_eq!(lows, regions);
+        assert_eq!(basins.basins.iter().map(|b| b.size).collect::<Vec<_>>(), vec![3, 9, 14, 9]);
+        assert_eq!(basins.labels.get(0, 0), Some(0));
+        assert_eq!(basins.labels.get(2, 0), None);
+        assert_eq!(basins.basins[0].boundary.len(), 3);
+        assert!(!basins.basins[2].boundary.contains(&Coord2D::new(2, 2)));
+
+        let mut ppm: Vec<u8> = Vec::new();
+        basins.write_ppm(&grid, &mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n10 5\n255\n"));
+        assert_eq!(ppm.len(), 12 + 10 * 5 * 3);
+    }
+
+    #[test]
+    fn day09_terrain_test() {
+        let input: Vec<String> = test_input("3321\n3411\n5678\n0789");
+        let grid = mkgrid(&input);
+        let lows = find_low_points(&grid, &Terrain::default());
+        assert_eq!(lows, vec![
+            LowRegion { height: 1, cells: vec![Coord2D::new(3, 0), Coord2D::new(3, 1), Coord2D::new(2, 1)] },
+            LowRegion { height: 0, cells: vec![Coord2D::new(0, 3)] },
+        ]);
+        let diag = Terrain { diagonal: true, ..Terrain::default() };
+        assert_eq!(find_low_points(&grid, &diag).len(), 2);
+        let lows = find_low_points(&grid, &Terrain { wall: 2, ..Terrain::default() });
+        assert_eq!(lows.iter().map(|r| r.height).collect::<Vec<_>>(), vec![1, 0]);
+
+        let input: Vec<String> = test_input("1291\n9929\n2911");
+        let grid = mkgrid(&input);
+        assert_eq!(label_basins(&grid, &Terrain::default()).basins.len(), 4);
+        let basins = label_basins(&grid, &diag);
+        assert_eq!(basins.basins.iter().map(|b| b.size).collect::<Vec<_>>(), vec![6, 1]);
+        let basins = label_basins(&grid, &Terrain { wall: 2, ..Terrain::default() });
+        assert_eq!(basins.basins.iter().map(|b| b.size).collect::<Vec<_>>(), vec![1, 1, 2]);
+    }
 }
@@ -1,15 +1,52 @@
+use std::collections::HashMap;
+use std::env;
 use std::vec::Vec;
 use ya_advent_lib::read::read_input;
 
+/// The bracket pairs making up a chunk language, plus characters that may
+/// appear anywhere and are skipped.
+struct Language {
+    closers: HashMap<char, char>,
+    ignore: Vec<char>,
+}
+
+impl Language {
+    fn new(pairs: &[(char, char)], ignore: &str) -> Self {
+        Self {
+            closers: pairs.iter().copied().collect(),
+            ignore: ignore.chars().collect(),
+        }
+    }
+    fn standard() -> Self {
+        Self::new(&[('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')], "")
+    }
+    fn is_closer(&self, c: char) -> bool {
+        self.closers.values().any(|v| *v == c)
+    }
+}
+
+#[derive(Debug,Eq,PartialEq)]
+struct Corruption {
+    /// 1-based column of the offending character.
+    col: usize,
+    /// The closer that was due, or `None` if nothing was open.
+    expected: Option<char>,
+    found: char,
+    /// Number of chunks open when the corruption was found.
+    depth: usize,
+}
+
+#[derive(Debug,Eq,PartialEq)]
 enum LineError {
-    Corrupt(char),
+    Corrupt(Corruption),
     Missing(String),
+    Unknown { col: usize, found: char },
 }
 
 impl LineError {
     fn corrupt(&self) -> Option<char> {
         match self {
-            LineError::Corrupt(c) => Some(*c),
+            LineError::Corrupt(c) => Some(c.found),
             _ => None,
         }
     }
@@ -21,61 +58,214 @@ impl LineError {
     }
 }
 
-fn analyze_line(line: &str) -> LineError {
+fn analyze_line(line: &str, lang: &Language) -> LineError {
     let mut stack: Vec<char> = Vec::new();
-    for c in line.chars() {
-        let top = if stack.is_empty() { ' ' } else { stack[stack.len() - 1] };
-        match c {
-            '<' => { stack.push('>'); },
-            '[' => { stack.push(']'); },
-            '(' => { stack.push(')'); },
-            '{' => { stack.push('}'); },
-            '>' | ']' | '}' | ')' => {
-                if top != c {
-                    return LineError::Corrupt(c);
-                }
-                stack.pop();
-            },
-            _ => panic!(),
+    for (i, c) in line.chars().enumerate() {
+        if let Some(closer) = lang.closers.get(&c) {
+            stack.push(*closer);
+        }
+        else if lang.is_closer(c) {
+            if stack.last() != Some(&c) {
+                return LineError::Corrupt(Corruption {
+                    col: i + 1,
+                    expected: stack.last().copied(),
+                    found: c,
+                    depth: stack.len(),
+                });
+            }
+            stack.pop();
+        }
+        else if !lang.ignore.contains(&c) {
+            return LineError::Unknown { col: i + 1, found: c };
         }
     }
     LineError::Missing(stack.iter().rev().collect())
 }
 
-fn score_corrupt(c: char) -> usize {
-    match c {
-        ')' => 3,
-        ']' => 57,
-        '}' => 1197,
-        '>' => 25137,
-        _ => panic!(),
+struct Scoring {
+    corrupt: HashMap<char, usize>,
+    missing: HashMap<char, usize>,
+    missing_base: usize,
+}
+
+impl Scoring {
+    fn standard() -> Self {
+        Self {
+            corrupt: HashMap::from([(')', 3), (']', 57), ('}', 1197), ('>', 25137)]),
+            missing: HashMap::from([(')', 1), (']', 2), ('}', 3), ('>', 4)]),
+            missing_base: 5,
+        }
+    }
+    /// `None` if the table has no score for `c`.
+    fn score_corrupt(&self, c: char) -> Option<usize> {
+        self.corrupt.get(&c).copied()
+    }
+    /// `None` if the table has no score for one of the closers.
+    fn score_missing(&self, s: &str) -> Option<usize> {
+        s.chars()
+            .try_fold(0, |acc, c| Some(acc * self.missing_base + self.missing.get(&c)?))
+    }
+}
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+enum Edit {
+    Keep(char),
+    Insert(char),
+    Delete(char),
+    Substitute(char, char),
+}
+
+#[derive(Clone,Copy)]
+enum Choice {
+    Empty,
+    Skip,
+    /// Pair the first character with the one at this index, as this pair.
+    Pair(usize, char, char),
+    /// Keep the first character and insert its closer at the end of the span.
+    Close(char),
+    Delete,
+}
+
+struct Repair {
+    edits: Vec<Edit>,
+}
+
+impl Repair {
+    fn cost(&self) -> usize {
+        self.edits.iter().filter(|e| !matches!(e, Edit::Keep(_))).count()
+    }
+    fn line(&self) -> String {
+        self.edits
+            .iter()
+            .filter_map(|e| match e {
+                Edit::Keep(c) | Edit::Insert(c) | Edit::Substitute(_, c) => Some(*c),
+                Edit::Delete(_) => None,
+            })
+            .collect()
+    }
+    /// The edits in word-diff notation: `[-x-]` for a deletion, `{+x+}` for
+    /// an insertion, both in a row for a substitution.
+    fn diff(&self) -> String {
+        self.edits
+            .iter()
+            .map(|e| match e {
+                Edit::Keep(c) => c.to_string(),
+                Edit::Insert(c) => format!("{{+{}+}}", c),
+                Edit::Delete(c) => format!("[-{}-]", c),
+                Edit::Substitute(a, b) => format!("[-{}-]{{+{}+}}", a, b),
+            })
+            .collect()
     }
 }
 
-fn score_missing(s: &str) -> usize {
-    s.chars()
-        .fold(0, |acc, c| acc * 5 + match c {
-            ')' => 1,
-            ']' => 2,
-            '}' => 3,
-            '>' => 4,
-            _ => panic!(),
-        })
+/// Finds a smallest set of single-character insertions, deletions and
+/// substitutions that turns `line` into a complete, valid line. Ties are
+/// broken towards keeping characters, so an incomplete line gets its closers
+/// appended.
+fn repair(line: &str, lang: &Language) -> Repair {
+    let chars: Vec<char> = line.chars().collect();
+    let n = chars.len();
+    let edit = |a: char, b: char| usize::from(a != b);
+    // cheapest way to make chars[i] and chars[k] a matched pair
+    let pair_cost: Vec<Vec<(usize, char, char)>> = (0..n)
+        .map(|i| (0..n)
+            .map(|k| lang.closers
+                .iter()
+                .map(|(o, c)| (edit(chars[i], *o) + edit(chars[k], *c), *o, *c))
+                .min()
+                .unwrap_or((usize::MAX, ' ', ' ')))
+            .collect())
+        .collect();
+    // cost[i][j] and choice[i][j] cover chars[i..j]
+    let mut cost = vec![vec![0; n + 1]; n + 1];
+    let mut choice = vec![vec![Choice::Empty; n + 1]; n + 1];
+    for len in 1..=n {
+        for i in 0..=(n - len) {
+            let j = i + len;
+            if lang.ignore.contains(&chars[i]) {
+                cost[i][j] = cost[i + 1][j];
+                choice[i][j] = Choice::Skip;
+                continue;
+            }
+            let mut best = (usize::MAX, Choice::Empty);
+            for k in (i + 1)..j {
+                if lang.ignore.contains(&chars[k]) {
+                    continue;
+                }
+                let (pc, o, c) = pair_cost[i][k];
+                let total = pc.saturating_add(cost[i + 1][k] + cost[k + 1][j]);
+                if total < best.0 {
+                    best = (total, Choice::Pair(k, o, c));
+                }
+            }
+            if let Some(closer) = lang.closers.get(&chars[i]) {
+                if 1 + cost[i + 1][j] < best.0 {
+                    best = (1 + cost[i + 1][j], Choice::Close(*closer));
+                }
+            }
+            if 1 + cost[i + 1][j] < best.0 {
+                best = (1 + cost[i + 1][j], Choice::Delete);
+            }
+            cost[i][j] = best.0;
+            choice[i][j] = best.1;
+        }
+    }
+
+    // spans are expanded depth-first; an edit that has to wait until a
+    // span is done (the closer of a pair) is queued as an empty span carrying it
+    let mut edits = Vec::with_capacity(n);
+    let mut work: Vec<(usize, usize, Option<Edit>)> = vec![(0, n, None)];
+    while let Some((i, j, emit)) = work.pop() {
+        if let Some(e) = emit {
+            edits.push(e);
+            continue;
+        }
+        if i >= j {
+            continue;
+        }
+        match choice[i][j] {
+            Choice::Empty => {},
+            Choice::Skip => {
+                edits.push(Edit::Keep(chars[i]));
+                work.push((i + 1, j, None));
+            },
+            Choice::Pair(k, o, c) => {
+                edits.push(if chars[i] == o { Edit::Keep(o) } else { Edit::Substitute(chars[i], o) });
+                work.push((k + 1, j, None));
+                work.push((0, 0, Some(if chars[k] == c { Edit::Keep(c) } else { Edit::Substitute(chars[k], c) })));
+                work.push((i + 1, k, None));
+            },
+            Choice::Close(c) => {
+                edits.push(Edit::Keep(chars[i]));
+                work.push((0, 0, Some(Edit::Insert(c))));
+                work.push((i + 1, j, None));
+            },
+            Choice::Delete => {
+                edits.push(Edit::Delete(chars[i]));
+                work.push((i + 1, j, None));
+            },
+        }
+    }
+    Repair { edits }
 }
 
 fn part1(input: &[String]) -> usize {
+    let lang = Language::standard();
+    let scoring = Scoring::standard();
     input
         .iter()
-        .map(|s| analyze_line(s))
-        .filter_map(|opt| opt.corrupt().map(score_corrupt))
+        .map(|s| analyze_line(s, &lang))
+        .filter_map(|opt| opt.corrupt().map(|c| scoring.score_corrupt(c).expect("standard scoring covers every closer")))
         .sum()
 }
 
 fn part2(input: &[String]) -> usize {
+    let lang = Language::standard();
+    let scoring = Scoring::standard();
     let mut scores: Vec<usize> = input
         .iter()
-        .map(|s| analyze_line(s))
-        .filter_map(|opt| opt.missing().map(|v| score_missing(v)))
+        .map(|s| analyze_line(s, &lang))
+        .filter_map(|opt| opt.missing().map(|v| scoring.score_missing(v).expect("standard scoring covers every closer")))
         .collect();
     scores.sort();
     scores[scores.len() / 2]
@@ -85,6 +275,14 @@ fn main() {
     let input: Vec<String> = read_input();
     println!("Part 1: {}", part1(&input));
     println!("Part 2: {}", part2(&input));
+
+    // DAY10_REPAIR=1 also shows the cheapest fix for every broken line
+    if env::var_os("DAY10_REPAIR").is_some() {
+        let lang = Language::standard();
+        for r in input.iter().map(|s| repair(s, &lang)).filter(|r| r.cost() > 0) {
+            println!("{:>3} {} -> {}", r.cost(), r.diff(), r.line());
+        }
+    }
 }
 
 #[cfg(test)]
@@ -98,4 +296,63 @@ mod tests {
         assert_eq!(part1(&input), 26397);
         assert_eq!(part2(&input), 288957);
     }
+
+    #[test]
+    fn day10_language_test() {
+        let lang = Language::standard();
+        assert_eq!(analyze_line("{([(<{}[<>[]}>{[]{[(<()>", &lang), LineError::Corrupt(Corruption {
+            col: 13, expected: Some(']'), found: '}', depth: 6,
+        }));
+        assert_eq!(analyze_line("())", &lang), LineError::Corrupt(Corruption {
+            col: 3, expected: None, found: ')', depth: 0,
+        }));
+        assert_eq!(analyze_line("(a)", &lang), LineError::Unknown { col: 2, found: 'a' });
+
+        let lang = Language::new(&[('(', ')'), ('«', '»')], " ,");
+        assert_eq!(analyze_line("(«a»)", &lang), LineError::Unknown { col: 3, found: 'a' });
+        assert_eq!(analyze_line("( «, » ) («", &lang), LineError::Missing("»)".into()));
+        assert_eq!(analyze_line("(«)", &lang), LineError::Corrupt(Corruption {
+            col: 3, expected: Some('»'), found: ')', depth: 2,
+        }));
+        let scoring = Scoring {
+            corrupt: HashMap::from([(')', 1), ('»', 10)]),
+            missing: HashMap::from([(')', 1), ('»', 2)]),
+            missing_base: 3,
+        };
+        assert_eq!(scoring.score_corrupt(')'), Some(1));
+        assert_eq!(scoring.score_missing("»)"), Some(7));
+        // the standard table knows nothing of guillemets
+        let standard = Scoring::standard();
+        assert_eq!(standard.score_corrupt('»'), None);
+        assert_eq!(standard.score_missing(")»]"), None);
+        assert_eq!(standard.score_missing(""), Some(0));
+    }
+
+    #[test]
+    fn day10_repair_test() {
+        let lang = Language::standard();
+        let r = repair("{([(<{}[<>[]}>{[]{[(<()>", &lang);
+        assert_eq!(r.cost(), 5);
+        assert!(matches!(analyze_line(&r.line(), &lang), LineError::Missing(m) if m.is_empty()));
+        let r = repair("[({(<(())[]>[[{[]{<()<>>", &lang);
+        assert_eq!(r.line(), "[]{}<(())[]>[]{[]}<()<>>");
+        assert_eq!(r.cost(), 4);
+        assert_eq!(repair("((", &lang).line(), "()");
+        assert_eq!(repair("(((", &lang).line(), "()()");
+        let r = repair("[<>({}){}[([])<>]]", &lang);
+        assert_eq!(r.cost(), 0);
+        assert_eq!(r.diff(), "[<>({}){}[([])<>]]");
+        let r = repair("(]", &lang);
+        assert_eq!(r.cost(), 1);
+        assert_eq!(r.line(), "()");
+        assert_eq!(r.diff(), "([-]-]{+)+}");
+        let r = repair("(<)", &lang);
+        assert_eq!(r.line(), "(<>)");
+        assert_eq!(r.diff(), "(<{+>+})");
+        assert_eq!(repair("(x>", &lang).diff(), "([-x-]{+)+}[->-]");
+
+        let lang = Language::new(&[('(', ')')], " ");
+        assert_eq!(repair("( (", &lang).line(), "( )");
+        assert_eq!(repair("( ( ) ", &lang).diff(), "( ( {+)+}) ");
+    }
 }
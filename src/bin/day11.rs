@@ -1,4 +1,9 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::hash::{Hash, Hasher};
 use std::vec::Vec;
+use itertools::Itertools;
 use ya_advent_lib::coords::Coord2D;
 use ya_advent_lib::grid::Grid;
 use ya_advent_lib::read::read_input;
@@ -10,54 +15,202 @@ fn mkgrid(input: &[String]) -> Grid<u8> {
     })
 }
 
-fn simstep(grid: &mut Grid<u8>) -> usize {
-    let xb = grid.x_bounds();
-    let yb = grid.y_bounds();
-    let mut total_flashes = 0;
-    grid.iter_mut().for_each(|v| *v += 1);
-    loop {
-        let mut flashes = 0;
-        for y in yb.start..yb.end {
-            for x in xb.start..xb.end {
-                if grid.get(x, y) > 9 {
-                    grid.set(x, y, 0);
-                    flashes += 1;
-                    Coord2D::new(x, y)
-                        .neighbors8()
-                        .into_iter()
-                        .for_each(|n| {
-                            if n.x >= xb.start && n.x < xb.end && n.y >= yb.start && n.y < yb.end {
-                                let v = grid.get(n.x, n.y);
-                                if v != 0 {
-                                    grid.set(n.x, n.y, v + 1);
-                                }
-                            }
-                        });
+#[derive(Clone,Copy,Debug,Default,Eq,PartialEq)]
+struct StepStats {
+    flashes: usize,
+    /// Size of the largest group of adjacent octopuses that flashed.
+    largest_cascade: usize,
+    reset: usize,
+}
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+enum Topology {
+    Bounded,
+    /// Opposite edges are joined, so every cell has a full neighborhood.
+    Torus,
+}
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+enum Neighborhood {
+    Square,
+    /// Six neighbors, treating the grid as axial hex coordinates (rows are
+    /// sheared so that (x+1, y-1) and (x-1, y+1) are adjacent).
+    Hex,
+}
+
+#[derive(Clone,Copy,Debug)]
+struct Rules {
+    topology: Topology,
+    neighborhood: Neighborhood,
+    /// An octopus flashes once its energy exceeds this.
+    threshold: u8,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules { topology: Topology::Bounded, neighborhood: Neighborhood::Square, threshold: 9 }
+    }
+}
+
+#[derive(Debug,Eq,PartialEq)]
+struct Cycle {
+    /// First step whose state is part of the cycle (0 is the initial state).
+    start: usize,
+    period: usize,
+}
+
+#[derive(Clone)]
+struct Octopi {
+    grid: Grid<u8>,
+    rules: Rules,
+    // union-find over flat cell indices, only meaningful for cells that
+    // flashed during the current step
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl Octopi {
+    fn new(grid: &Grid<u8>, rules: Rules) -> Self {
+        let n = grid.iter().count();
+        Self {
+            grid: grid.clone(),
+            rules,
+            parent: vec![0; n],
+            size: vec![0; n],
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.parent.len()
+    }
+
+    fn index(&self, c: Coord2D) -> usize {
+        let xb = self.grid.x_bounds();
+        let yb = self.grid.y_bounds();
+        ((c.y - yb.start) * (xb.end - xb.start) + c.x - xb.start) as usize
+    }
+
+    fn neighbors(&self, c: Coord2D) -> Vec<Coord2D> {
+        let adjacent = match self.rules.neighborhood {
+            Neighborhood::Square => c.neighbors8(),
+            Neighborhood::Hex => [(1, 0), (-1, 0), (0, 1), (0, -1), (1, -1), (-1, 1)]
+                .into_iter()
+                .map(|d| c + d)
+                .collect(),
+        };
+        match self.rules.topology {
+            Topology::Bounded => adjacent
+                .into_iter()
+                .filter(|n| self.grid.contains_coord(*n))
+                .collect(),
+            Topology::Torus => {
+                let xb = self.grid.x_bounds();
+                let yb = self.grid.y_bounds();
+                adjacent
+                    .into_iter()
+                    .map(|n| Coord2D::new(
+                        (n.x - xb.start).rem_euclid(xb.end - xb.start) + xb.start,
+                        (n.y - yb.start).rem_euclid(yb.end - yb.start) + yb.start,
+                    ))
+                    .filter(|n| *n != c)
+                    .unique()
+                    .collect()
+            },
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            let (big, small) = if self.size[a] >= self.size[b] { (a, b) } else { (b, a) };
+            self.parent[small] = big;
+            self.size[big] += self.size[small];
+        }
+    }
+
+    fn step(&mut self) -> StepStats {
+        let threshold = self.rules.threshold;
+        let mut work: Vec<Coord2D> = Vec::new();
+        self.grid.iter_mut().for_each(|v| *v = v.saturating_add(1));
+        self.grid.for_each(|v, x, y| if v > threshold { work.push(Coord2D::new(x, y)); });
+        let mut flashed: Vec<usize> = Vec::new();
+        while let Some(c) = work.pop() {
+            let idx = self.index(c);
+            self.grid.set_c(c, 0);
+            self.parent[idx] = idx;
+            self.size[idx] = 1;
+            flashed.push(idx);
+            for n in self.neighbors(c) {
+                match self.grid.get_c(n) {
+                    0 => self.union(idx, self.index(n)),
+                    v => {
+                        // a cell is queued exactly once, when it first passes the threshold
+                        self.grid.set_c(n, v.saturating_add(1));
+                        if v == threshold {
+                            work.push(n);
+                        }
+                    },
                 }
             }
         }
-        if flashes == 0 { break; }
-        total_flashes += flashes;
+        let largest_cascade = flashed
+            .iter()
+            .map(|i| self.size[*i])
+            .max()
+            .unwrap_or(0);
+        StepStats {
+            flashes: flashed.len(),
+            largest_cascade,
+            reset: self.grid.iter().filter(|v| **v == 0).count(),
+        }
+    }
+
+    fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.grid.data().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Steps until the grid returns to an earlier state, giving up after
+    /// `limit` steps. States are remembered by hash only; a hit is confirmed
+    /// by checking that the current state really recurs after the period.
+    fn find_cycle(&mut self, limit: usize) -> Option<Cycle> {
+        let mut seen: HashMap<u64, usize> = HashMap::new();
+        for step in 0..=limit {
+            if let Some(start) = seen.insert(self.state_hash(), step) {
+                let period = step - start;
+                let mut check = self.clone();
+                (0..period).for_each(|_| { check.step(); });
+                if check.grid.data() == self.grid.data() {
+                    return Some(Cycle { start, period });
+                }
+            }
+            self.step();
+        }
+        None
     }
-    total_flashes
 }
 
 fn part1(grid: &Grid<u8>) -> usize {
-    let mut grid = grid.clone();
-    let mut flashes = 0;
-    for _ in 0..100 {
-        flashes += simstep(&mut grid);
-    }
-    flashes
+    let mut octopi = Octopi::new(grid, Rules::default());
+    (0..100).map(|_| octopi.step().flashes).sum()
 }
 
 fn part2(grid: &Grid<u8>) -> usize {
-    let mut grid = grid.clone();
-    let n_octos = grid.iter().count();
+    let mut octopi = Octopi::new(grid, Rules::default());
+    let n_octos = octopi.len();
     let mut iters = 0;
     loop {
         iters += 1;
-        if simstep(&mut grid) >= n_octos {
+        if octopi.step().flashes >= n_octos {
             break;
         }
     }
@@ -70,6 +223,28 @@ fn main() {
 
     println!("Part 1: {}", part1(&grid));
     println!("Part 2: {}", part2(&grid));
+
+    // DAY11_CYCLE=<limit> also looks for a repeating state within that many
+    // steps, on the grid shape given by DAY11_TOPOLOGY and DAY11_NEIGHBORHOOD
+    if let Ok(limit) = env::var("DAY11_CYCLE") {
+        let Ok(limit) = limit.parse() else {
+            eprintln!("DAY11_CYCLE must be a number of steps");
+            std::process::exit(1);
+        };
+        let topology = match env::var("DAY11_TOPOLOGY").as_deref() {
+            Ok("torus") => Topology::Torus,
+            _ => Topology::Bounded,
+        };
+        let neighborhood = match env::var("DAY11_NEIGHBORHOOD").as_deref() {
+            Ok("hex") => Neighborhood::Hex,
+            _ => Neighborhood::Square,
+        };
+        let rules = Rules { topology, neighborhood, ..Rules::default() };
+        match Octopi::new(&grid, rules).find_cycle(limit) {
+            Some(Cycle { start, period }) => println!("Cycle: from step {} every {} steps", start, period),
+            None => println!("No cycle within {} steps", limit),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -84,4 +259,49 @@ mod tests {
         assert_eq!(part1(&grid), 1656);
         assert_eq!(part2(&grid), 195);
     }
+
+    #[test]
+    fn day11_stats_test() {
+        let input: Vec<String> = test_input("11111\n19991\n19191\n19991\n11111");
+        let mut octopi = Octopi::new(&mkgrid(&input), Rules::default());
+        assert_eq!(octopi.step(), StepStats { flashes: 9, largest_cascade: 9, reset: 9 });
+        assert_eq!(octopi.step(), StepStats { flashes: 0, largest_cascade: 0, reset: 0 });
+
+        let input: Vec<String> = test_input(include_str!("day11.testinput"));
+        let mut octopi = Octopi::new(&mkgrid(&input), Rules::default());
+        let stats: Vec<StepStats> = (0..195).map(|_| octopi.step()).collect();
+        assert_eq!(stats[1], StepStats { flashes: 35, largest_cascade: 26, reset: 35 });
+        assert_eq!(stats[194].largest_cascade, 100);
+        assert!(stats.iter().all(|s| s.largest_cascade <= s.flashes));
+    }
+
+    #[test]
+    fn day11_rules_test() {
+        let input: Vec<String> = test_input(include_str!("day11.testinput"));
+        let grid = mkgrid(&input);
+        let mut octopi = Octopi::new(&grid, Rules::default());
+        assert_eq!(octopi.find_cycle(1000), Some(Cycle { start: 195, period: 10 }));
+
+        let input: Vec<String> = test_input("900\n000\n000");
+        let grid = mkgrid(&input);
+        let mut bounded = Octopi::new(&grid, Rules::default());
+        let mut torus = Octopi::new(&grid, Rules { topology: Topology::Torus, ..Rules::default() });
+        assert_eq!(bounded.neighbors(Coord2D::new(0, 0)).len(), 3);
+        assert_eq!(torus.neighbors(Coord2D::new(0, 0)).len(), 8);
+        bounded.step();
+        torus.step();
+        assert_eq!(bounded.grid.data(), &vec![0, 2, 1, 2, 2, 1, 1, 1, 1]);
+        assert_eq!(torus.grid.data(), &vec![0, 2, 2, 2, 2, 2, 2, 2, 2]);
+
+        let hex = Octopi::new(&grid, Rules { neighborhood: Neighborhood::Hex, ..Rules::default() });
+        assert_eq!(hex.neighbors(Coord2D::new(1, 1)).len(), 6);
+        assert!(!hex.neighbors(Coord2D::new(1, 1)).contains(&Coord2D::new(0, 0)));
+
+        let mut slow = Octopi::new(&grid, Rules { threshold: 11, ..Rules::default() });
+        assert_eq!(slow.step().flashes, 0);
+        assert_eq!(slow.step().flashes, 0);
+        assert_eq!(slow.step().flashes, 1);
+        let mut slow = Octopi::new(&grid, Rules { threshold: 11, ..Rules::default() });
+        assert_eq!(slow.find_cycle(100), Some(Cycle { start: 22, period: 12 }));
+    }
 }
@@ -1,4 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::fmt;
+use std::fs;
 use std::str::FromStr;
 use std::vec::Vec;
 use itertools::Itertools;
@@ -49,57 +52,454 @@ fn mkcaves(input: &[Edge]) -> HashMap<String, Cave> {
     caves
 }
 
-fn traverse(current: &str, caves: &HashMap<String, Cave>, visited: &HashSet<String>, repeat: Option<&str>) -> Vec<String> {
-    if current == "end" {
-        return vec!["end".into()];
+#[derive(Debug,Eq,PartialEq)]
+enum CaveError {
+    MissingCave(&'static str),
+    /// Two large caves are joined, so a path could bounce between them forever.
+    InfinitePaths(String, String),
+    /// More small caves than fit in the visited-set bitmask.
+    TooManySmallCaves(usize),
+}
+
+impl fmt::Display for CaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaveError::MissingCave(name) => write!(f, "no {} cave", name),
+            CaveError::InfinitePaths(a, b) =>
+                write!(f, "large caves {} and {} are connected; path count is infinite", a, b),
+            CaveError::TooManySmallCaves(n) =>
+                write!(f, "{} small caves; at most {} are supported", n, u128::BITS),
+        }
+    }
+}
+
+/// Graphviz rendering of the cave system: large caves as filled boxes,
+/// small caves as ellipses, and start/end double-circled.
+fn to_dot(caves: &HashMap<String, Cave>) -> String {
+    let mut out = String::from("graph caves {\n");
+    for name in caves.keys().sorted() {
+        let style = if name == "start" || name == "end" {
+            "shape=doublecircle"
+        } else if caves[name].is_large {
+            "shape=box, style=filled, fillcolor=lightblue"
+        } else {
+            "shape=ellipse"
+        };
+        out.push_str(&format!("    \"{}\" [{}];\n", name, style));
+    }
+    for (a, cave) in caves.iter().sorted_by_key(|(k, _)| *k) {
+        for b in cave.neighbors.iter().sorted().filter(|b| a < *b) {
+            out.push_str(&format!("    \"{}\" -- \"{}\";\n", a, b));
+        }
     }
-    let mut visited = visited.clone();
-    let mut repeat = repeat;
-    match repeat {
-        Some(r) => {
-            if r == current {
-                repeat = None;
-            } else {
-                visited.insert(current.to_owned());
+    out.push_str("}\n");
+    out
+}
+
+#[derive(Debug,Default,Eq,PartialEq)]
+struct CaveReport {
+    /// Caves whose removal would split the system in two.
+    articulation_points: Vec<String>,
+    unreachable: Vec<String>,
+    /// Groups of large caves joined directly to each other.
+    large_cycles: Vec<Vec<String>>,
+}
+
+fn analyze(caves: &HashMap<String, Cave>) -> CaveReport {
+    let names: Vec<&String> = caves.keys().sorted().collect();
+    let id: HashMap<&String, usize> = names.iter().enumerate().map(|(i, n)| (*n, i)).collect();
+    let adj: Vec<Vec<usize>> = names
+        .iter()
+        .map(|n| caves[*n].neighbors.iter().map(|m| id[m]).sorted().collect())
+        .collect();
+
+    let mut reached = vec![false; names.len()];
+    if let Some(&start) = id.get(&"start".to_string()) {
+        let mut queue = VecDeque::from([start]);
+        reached[start] = true;
+        while let Some(c) = queue.pop_front() {
+            for &n in &adj[c] {
+                if !reached[n] {
+                    reached[n] = true;
+                    queue.push_back(n);
+                }
             }
         }
-        None => {
-            visited.insert(current.to_owned());
+    }
+
+    // Tarjan's low-link search, run from every unvisited cave
+    fn low_link(c: usize, parent: Option<usize>, adj: &[Vec<usize>], depth: &mut [Option<usize>],
+                low: &mut [usize], cut: &mut [bool], d: usize) {
+        depth[c] = Some(d);
+        low[c] = d;
+        let mut children = 0;
+        for &n in &adj[c] {
+            match depth[n] {
+                None => {
+                    children += 1;
+                    low_link(n, Some(c), adj, depth, low, cut, d + 1);
+                    low[c] = low[c].min(low[n]);
+                    if parent.is_some() && low[n] >= d {
+                        cut[c] = true;
+                    }
+                },
+                Some(nd) if Some(n) != parent => low[c] = low[c].min(nd),
+                _ => {},
+            }
+        }
+        if parent.is_none() && children > 1 {
+            cut[c] = true;
         }
     }
-    let cave = &caves[current];
-    let mut paths = Vec::new();
-    for n in &cave.neighbors {
-        let neighbor = &caves[n];
-        if neighbor.is_large || !visited.contains(n) {
-            for p in traverse(n, caves, &visited, repeat) {
-                paths.push(format!("{current},{}", p));
+    let mut depth = vec![None; names.len()];
+    let mut low = vec![0; names.len()];
+    let mut cut = vec![false; names.len()];
+    for c in 0..names.len() {
+        if depth[c].is_none() {
+            low_link(c, None, &adj, &mut depth, &mut low, &mut cut, 0);
+        }
+    }
+
+    let mut grouped = vec![false; names.len()];
+    let mut large_cycles = Vec::new();
+    for c in 0..names.len() {
+        if grouped[c] || !caves[names[c]].is_large {
+            continue;
+        }
+        let mut group = vec![c];
+        grouped[c] = true;
+        let mut i = 0;
+        while i < group.len() {
+            for &n in &adj[group[i]] {
+                if !grouped[n] && caves[names[n]].is_large {
+                    grouped[n] = true;
+                    group.push(n);
+                }
             }
+            i += 1;
         }
+        if group.len() > 1 {
+            large_cycles.push(group.iter().sorted().map(|g| names[*g].clone()).collect());
+        }
+    }
+
+    let pick = |f: &dyn Fn(usize) -> bool| (0..names.len()).filter(|c| f(*c)).map(|c| names[c].clone()).collect();
+    CaveReport {
+        articulation_points: pick(&|c| cut[c]),
+        unreachable: pick(&|c| !reached[c]),
+        large_cycles,
     }
-    paths
 }
 
-fn part1(caves: &HashMap<String, Cave>) -> usize {
-    let visited: HashSet<String> = HashSet::new();
-    traverse("start", caves, &visited, None).len()
+/// The cave system with caves interned to indices. Small caves also get a
+/// bit in the visited mask.
+struct CaveGraph {
+    names: Vec<String>,
+    bit: Vec<u128>,
+    adj: Vec<Vec<usize>>,
+    start: usize,
+    end: usize,
 }
 
-fn part2(caves: &HashMap<String, Cave>) -> usize {
-    let visited: HashSet<String> = HashSet::new();
-    caves
-        .iter()
-        .filter(|(k,v)| !v.is_large && *k != "start" && *k != "end")
-        .flat_map(|(k,_)| traverse("start", caves, &visited, Some(k)))
-        .unique()
-        .count()
+impl CaveGraph {
+    fn new(caves: &HashMap<String, Cave>) -> Result<Self, CaveError> {
+        for (name, cave) in caves.iter().sorted_by_key(|(k, _)| *k) {
+            if let Some(other) = cave.neighbors.iter().sorted().find(|n| cave.is_large && caves[*n].is_large) {
+                return Err(CaveError::InfinitePaths(name.clone(), other.clone()));
+            }
+        }
+        for name in ["start", "end"] {
+            if !caves.contains_key(name) {
+                return Err(CaveError::MissingCave(name));
+            }
+        }
+        let nsmall = caves.values().filter(|c| !c.is_large).count();
+        if nsmall > u128::BITS as usize {
+            return Err(CaveError::TooManySmallCaves(nsmall));
+        }
+        let names: Vec<String> = caves.keys().sorted().cloned().collect();
+        let id: HashMap<&str, usize> = names.iter().enumerate().map(|(i, n)| (n.as_str(), i)).collect();
+        let mut next = 0;
+        let bit = names
+            .iter()
+            .map(|n| if caves[n].is_large { 0 } else { next += 1; 1 << (next - 1) })
+            .collect();
+        let adj = names
+            .iter()
+            .map(|n| caves[n].neighbors.iter().map(|m| id[m.as_str()]).sorted().collect())
+            .collect();
+        Ok(Self {
+            start: id["start"],
+            end: id["end"],
+            names, bit, adj,
+        })
+    }
+
+    /// Counts paths from `start` to `end` that enter each small cave at most
+    /// once, except that with `allow_repeat` a single small cave other than
+    /// `start` may be entered twice.
+    fn count_paths(&self, allow_repeat: bool) -> usize {
+        let mut memo = HashMap::new();
+        self.count_from(self.start, self.bit[self.start], !allow_repeat, &mut memo)
+    }
+
+    fn count_from(&self, cave: usize, visited: u128, repeated: bool,
+                  memo: &mut HashMap<(usize, u128, bool), usize>) -> usize {
+        if cave == self.end {
+            return 1;
+        }
+        if let Some(n) = memo.get(&(cave, visited, repeated)) {
+            return *n;
+        }
+        let mut count = 0;
+        for &next in &self.adj[cave] {
+            if visited & self.bit[next] == 0 {
+                count += self.count_from(next, visited | self.bit[next], repeated, memo);
+            }
+            else if !repeated && next != self.start {
+                count += self.count_from(next, visited, true, memo);
+            }
+        }
+        memo.insert((cave, visited, repeated), count);
+        count
+    }
+}
+
+/// Which caves a path may enter and how often. By default a small cave may
+/// be entered once and a large cave any number of times; `start` is never
+/// re-entered and a path stops as soon as it reaches `end`.
+#[derive(Clone,Default)]
+struct VisitPolicy {
+    /// Entries beyond a cave's limit allowed over the whole path.
+    repeats: usize,
+    /// Per-cave entry limits, overriding the default for that cave.
+    limits: HashMap<String, usize>,
+    forbidden: HashSet<String>,
+    /// Caves every path must pass through, in any order.
+    waypoints: Vec<String>,
+}
+
+/// A `VisitPolicy` resolved against a particular graph.
+struct Budget {
+    repeats: usize,
+    limit: Vec<Option<usize>>,
+    forbidden: Vec<bool>,
+    waypoints: Vec<usize>,
+    /// Whether a waypoint can't be found in the graph at all.
+    impossible: bool,
+}
+
+impl Budget {
+    fn new(graph: &CaveGraph, policy: &VisitPolicy) -> Self {
+        let limit = graph.names
+            .iter()
+            .enumerate()
+            .map(|(i, n)| policy.limits.get(n).copied().or(if graph.bit[i] == 0 { None } else { Some(1) }))
+            .collect();
+        let forbidden = graph.names
+            .iter()
+            .enumerate()
+            .map(|(i, n)| policy.forbidden.contains(n) || i == graph.start)
+            .collect();
+        let waypoints: Vec<usize> = policy.waypoints
+            .iter()
+            .filter_map(|w| graph.names.iter().position(|n| n == w))
+            .collect();
+        Self {
+            repeats: policy.repeats,
+            limit,
+            forbidden,
+            impossible: waypoints.len() < policy.waypoints.len(),
+            waypoints,
+        }
+    }
+
+    /// Whether `cave` may be entered with the given counts, and if so
+    /// whether doing so uses up a repeat.
+    fn can_enter(&self, cave: usize, counts: &[u8], repeats: usize) -> Option<bool> {
+        if self.forbidden[cave] {
+            None
+        }
+        else if self.limit[cave].is_none_or(|l| (counts[cave] as usize) < l) {
+            Some(false)
+        }
+        else if repeats < self.repeats {
+            Some(true)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Entry counts are only tracked where they matter, so that unlimited
+    /// caves don't blow up the memo state.
+    fn tracked(&self, cave: usize) -> bool {
+        self.limit[cave].is_some() || self.waypoints.contains(&cave)
+    }
+
+    fn done(&self, counts: &[u8]) -> bool {
+        self.waypoints.iter().all(|w| counts[*w] > 0)
+    }
+}
+
+/// Depth-first path enumeration under a `VisitPolicy`, one path at a time.
+struct Paths<'a> {
+    graph: &'a CaveGraph,
+    budget: Budget,
+    path: Vec<usize>,
+    next: Vec<usize>,
+    used_repeat: Vec<bool>,
+    counts: Vec<u8>,
+    repeats: usize,
+}
+
+impl<'a> Paths<'a> {
+    fn enter(&mut self, cave: usize, repeat: bool) {
+        self.path.push(cave);
+        self.next.push(0);
+        self.used_repeat.push(repeat);
+        if self.budget.tracked(cave) {
+            self.counts[cave] += 1;
+        }
+        self.repeats += usize::from(repeat);
+    }
+
+    fn leave(&mut self) {
+        let cave = self.path.pop().unwrap();
+        self.next.pop();
+        if self.budget.tracked(cave) {
+            self.counts[cave] -= 1;
+        }
+        self.repeats -= usize::from(self.used_repeat.pop().unwrap());
+    }
+
+    fn names(&self) -> Vec<&'a str> {
+        self.path.iter().map(|c| self.graph.names[*c].as_str()).collect()
+    }
+}
+
+impl<'a> Iterator for Paths<'a> {
+    type Item = Vec<&'a str>;
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(&cave) = self.path.last() {
+            let i = *self.next.last().unwrap();
+            if i == self.graph.adj[cave].len() {
+                self.leave();
+                continue;
+            }
+            *self.next.last_mut().unwrap() += 1;
+            let next = self.graph.adj[cave][i];
+            if let Some(repeat) = self.budget.can_enter(next, &self.counts, self.repeats) {
+                self.enter(next, repeat);
+                if next == self.graph.end {
+                    let found = self.budget.done(&self.counts).then(|| self.names());
+                    self.leave();
+                    if found.is_some() {
+                        return found;
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl CaveGraph {
+    fn paths(&self, policy: &VisitPolicy) -> Paths<'_> {
+        let budget = Budget::new(self, policy);
+        let mut paths = Paths {
+            graph: self,
+            path: Vec::new(),
+            next: Vec::new(),
+            used_repeat: Vec::new(),
+            counts: vec![0; self.names.len()],
+            repeats: 0,
+            budget,
+        };
+        if !paths.budget.impossible {
+            paths.enter(self.start, false);
+        }
+        paths
+    }
+
+    fn count_with(&self, policy: &VisitPolicy) -> usize {
+        let budget = Budget::new(self, policy);
+        if budget.impossible {
+            return 0;
+        }
+        let mut counts = vec![0; self.names.len()];
+        if budget.tracked(self.start) {
+            counts[self.start] = 1;
+        }
+        self.count_budget(self.start, &budget, &mut counts, 0, &mut HashMap::new())
+    }
+
+    fn count_budget(&self, cave: usize, budget: &Budget, counts: &mut Vec<u8>, repeats: usize,
+                    memo: &mut HashMap<(usize, Vec<u8>, usize), usize>) -> usize {
+        if cave == self.end {
+            return usize::from(budget.done(counts));
+        }
+        let key = (cave, counts.clone(), repeats);
+        if let Some(n) = memo.get(&key) {
+            return *n;
+        }
+        let mut count = 0;
+        for &next in &self.adj[cave] {
+            if let Some(repeat) = budget.can_enter(next, counts, repeats) {
+                let tracked = budget.tracked(next);
+                if tracked {
+                    counts[next] += 1;
+                }
+                count += self.count_budget(next, budget, counts, repeats + usize::from(repeat), memo);
+                if tracked {
+                    counts[next] -= 1;
+                }
+            }
+        }
+        memo.insert(key, count);
+        count
+    }
+}
+
+fn part1(graph: &CaveGraph) -> usize {
+    graph.count_paths(false)
+}
+
+fn part2(graph: &CaveGraph) -> usize {
+    graph.count_paths(true)
 }
 
 fn main() {
     let input: Vec<Edge> = read_input();
     let caves = mkcaves(&input);
-    println!("Part 1: {}", part1(&caves));
-    println!("Part 2: {}", part2(&caves));
+    for cave in analyze(&caves).unreachable {
+        eprintln!("warning: {} is unreachable from start", cave);
+    }
+    // DAY12_DOT=<file> also writes the cave system for Graphviz
+    if let Ok(path) = env::var("DAY12_DOT") {
+        if let Err(e) = fs::write(&path, to_dot(&caves)) {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    let graph = match CaveGraph::new(&caves) {
+        Ok(g) => g,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+    println!("Part 1: {}", part1(&graph));
+    println!("Part 2: {}", part2(&graph));
+
+    // DAY12_PATHS=<n> also lists the paths allowed n extra small-cave entries
+    if let Some(repeats) = env::var("DAY12_PATHS").ok().and_then(|v| v.parse().ok()) {
+        let policy = VisitPolicy { repeats, ..Default::default() };
+        for path in graph.paths(&policy) {
+            println!("{}", path.join(","));
+        }
+        println!("{} paths", graph.count_with(&policy));
+    }
 }
 
 #[cfg(test)]
@@ -110,16 +510,98 @@ mod tests {
     #[test]
     fn day12_test() {
         let input1: Vec<Edge> = test_input(include_str!("day12.test1input"));
-        let caves1 = mkcaves(&input1);
+        let caves1 = CaveGraph::new(&mkcaves(&input1)).unwrap();
         assert_eq!(part1(&caves1), 10);
         assert_eq!(part2(&caves1), 36);
         let input2: Vec<Edge> = test_input(include_str!("day12.test2input"));
-        let caves2 = mkcaves(&input2);
+        let caves2 = CaveGraph::new(&mkcaves(&input2)).unwrap();
         assert_eq!(part1(&caves2), 19);
         assert_eq!(part2(&caves2), 103);
         let input3: Vec<Edge> = test_input(include_str!("day12.test3input"));
-        let caves3 = mkcaves(&input3);
+        let caves3 = CaveGraph::new(&mkcaves(&input3)).unwrap();
         assert_eq!(part1(&caves3), 226);
         assert_eq!(part2(&caves3), 3509);
     }
+
+    #[test]
+    fn day12_large_graph_test() {
+        // a chain of 48 small caves where each step can go direct or through
+        // a large cave
+        let mut lines: Vec<String> = vec!["start-s0".into(), "s47-end".into()];
+        for i in 0..47 {
+            lines.push(format!("s{}-s{}", i, i + 1));
+            lines.push(format!("s{}-L{}", i, i));
+            lines.push(format!("L{}-s{}", i, i + 1));
+        }
+        let input: Vec<Edge> = test_input(&lines.join("\n"));
+        let graph = CaveGraph::new(&mkcaves(&input)).unwrap();
+        assert_eq!(graph.adj.len(), 97);
+        assert_eq!(graph.count_paths(false), 1 << 47);
+        assert!(graph.count_paths(true) > 1 << 47);
+    }
+
+    #[test]
+    fn day12_policy_test() {
+        let input: Vec<Edge> = test_input(include_str!("day12.test1input"));
+        let graph = CaveGraph::new(&mkcaves(&input)).unwrap();
+        let mut policy = VisitPolicy::default();
+        assert_eq!(graph.count_with(&policy), 10);
+        assert_eq!(graph.paths(&policy).count(), 10);
+        assert!(graph.paths(&policy).contains(&vec!["start", "A", "c", "A", "b", "A", "end"]));
+        policy.repeats = 1;
+        assert_eq!(graph.count_with(&policy), 36);
+        assert_eq!(graph.paths(&policy).count(), 36);
+        policy.repeats = 2;
+        assert_eq!(graph.count_with(&policy), graph.paths(&policy).count());
+        assert!(graph.count_with(&policy) > 36);
+
+        let policy = VisitPolicy { forbidden: HashSet::from(["A".into()]), ..Default::default() };
+        assert_eq!(graph.paths(&policy).collect::<Vec<_>>(), vec![vec!["start", "b", "end"]]);
+        let policy = VisitPolicy { limits: HashMap::from([("A".into(), 1)]), ..Default::default() };
+        assert_eq!(graph.count_with(&policy), 4);
+        assert_eq!(graph.paths(&policy).count(), 4);
+        let policy = VisitPolicy { waypoints: vec!["c".into(), "d".into()], repeats: 1, ..Default::default() };
+        assert_eq!(graph.count_with(&policy), graph.paths(&policy).count());
+        assert!(graph.paths(&policy).all(|p| p.contains(&"c") && p.contains(&"d")));
+        let policy = VisitPolicy { waypoints: vec!["nowhere".into()], ..Default::default() };
+        assert_eq!(graph.count_with(&policy), 0);
+        assert_eq!(graph.paths(&policy).next(), None);
+
+        let input: Vec<Edge> = test_input(include_str!("day12.test3input"));
+        let graph = CaveGraph::new(&mkcaves(&input)).unwrap();
+        let policy = VisitPolicy { repeats: 1, ..Default::default() };
+        assert_eq!(graph.count_with(&policy), 3509);
+        assert_eq!(graph.paths(&policy).count(), 3509);
+    }
+
+    #[test]
+    fn day12_analysis_test() {
+        let input: Vec<Edge> = test_input(include_str!("day12.test1input"));
+        let caves = mkcaves(&input);
+        let dot = to_dot(&caves);
+        assert!(dot.starts_with("graph caves {\n    \"A\" [shape=box"));
+        assert!(dot.contains("    \"b\" [shape=ellipse];\n"));
+        assert!(dot.contains("    \"start\" [shape=doublecircle];\n"));
+        assert!(dot.contains("    \"A\" -- \"end\";\n"));
+        assert_eq!(dot.matches(" -- ").count(), 7);
+        assert_eq!(analyze(&caves), CaveReport {
+            articulation_points: vec!["A".into(), "b".into()],
+            unreachable: vec![],
+            large_cycles: vec![],
+        });
+
+        let input: Vec<Edge> = test_input("start-A\nA-B\nB-end\nB-c\nx-y");
+        let caves = mkcaves(&input);
+        assert_eq!(analyze(&caves), CaveReport {
+            articulation_points: vec!["A".into(), "B".into()],
+            unreachable: vec!["x".into(), "y".into()],
+            large_cycles: vec![vec!["A".into(), "B".into()]],
+        });
+        assert_eq!(CaveGraph::new(&caves).err(), Some(CaveError::InfinitePaths("A".into(), "B".into())));
+        let caves = mkcaves(&test_input::<Edge>("start-a"));
+        assert_eq!(CaveGraph::new(&caves).err(), Some(CaveError::MissingCave("end")));
+        // 129 small caves plus start and end
+        let input: Vec<Edge> = test_input(&(0..129).map(|i| format!("start-c{}\nc{}-end", i, i)).join("\n"));
+        assert_eq!(CaveGraph::new(&mkcaves(&input)).err(), Some(CaveError::TooManySmallCaves(131)));
+    }
 }
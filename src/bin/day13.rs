@@ -1,29 +1,38 @@
 #[macro_use] extern crate lazy_static;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
 use std::str::FromStr;
 use std::vec::Vec;
+use itertools::Itertools;
 use regex::Regex;
 use ya_advent_lib::coords::Coord2D;
 use ya_advent_lib::read::read_sectioned_input;
 use ya_advent_lib::infinite_grid::InfiniteGrid;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum Fold {
     X(i64),
     Y(i64),
+    /// Along the line `x - y = c`; dots with `x - y > c` fold over.
+    Diag(i64),
+    /// Along the line `x + y = c`; dots with `x + y > c` fold over.
+    AntiDiag(i64),
 }
 
 impl FromStr for Fold {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(r"([xy])=(\d+)").unwrap();
+            static ref RE: Regex = Regex::new(r"(x-y|x\+y|x|y)=(-?\d+)").unwrap();
         }
         if let Some(caps) = RE.captures(s) {
             let v = caps.get(2).unwrap().as_str().parse::<i64>().unwrap();
-            if caps.get(1).unwrap().as_str() == "x" {
-                Ok(Fold::X(v))
-            } else {
-                Ok(Fold::Y(v))
+            match caps.get(1).unwrap().as_str() {
+                "x" => Ok(Fold::X(v)),
+                "y" => Ok(Fold::Y(v)),
+                "x-y" => Ok(Fold::Diag(v)),
+                _ => Ok(Fold::AntiDiag(v)),
             }
         }
         else {
@@ -34,60 +43,185 @@ impl FromStr for Fold {
 
 type Input = (Vec<Coord2D>, Vec<Fold>);
 
-fn setup(input: &Input) -> (InfiniteGrid<bool>, Vec<Fold>) {
-    let mut grid = InfiniteGrid::new(false);
-    let folds = input.1.clone();
-    for c in input.0.iter() {
-        grid.set(c.x, c.y, true);
+/// The dots on the paper, each with the input coordinates that have landed
+/// on it. The paper always spans `0..width` by `0..height`.
+#[derive(Clone)]
+struct Paper {
+    dots: HashMap<Coord2D, Vec<Coord2D>>,
+    width: i64,
+    height: i64,
+}
+
+impl Paper {
+    fn new(dots: &[Coord2D]) -> Self {
+        let mut paper = Paper { dots: HashMap::new(), width: 0, height: 0 };
+        for c in dots {
+            paper.dots.entry(*c).or_default().push(*c);
+        }
+        paper.fit();
+        paper
+    }
+
+    /// Shrinks the paper to the dots, shifting them back into positive
+    /// coordinates if a fold has pushed them past the top or left edge.
+    fn fit(&mut self) {
+        let min_x = self.dots.keys().map(|c| c.x).min().unwrap_or(0).min(0);
+        let min_y = self.dots.keys().map(|c| c.y).min().unwrap_or(0).min(0);
+        if min_x < 0 || min_y < 0 {
+            self.dots = self.dots
+                .drain()
+                .map(|(c, from)| (c - (min_x, min_y), from))
+                .collect();
+        }
+        self.width = self.dots.keys().map(|c| c.x + 1).max().unwrap_or(0);
+        self.height = self.dots.keys().map(|c| c.y + 1).max().unwrap_or(0);
+    }
+
+    fn grid(&self) -> InfiniteGrid<bool> {
+        let mut grid = InfiniteGrid::new(false);
+        for c in self.dots.keys() {
+            grid.set_c(*c, true);
+        }
+        grid
+    }
+
+    /// Each final dot with the input coordinates that landed on it.
+    fn provenance(&self) -> Vec<(Coord2D, Vec<Coord2D>)> {
+        self.dots
+            .iter()
+            .map(|(c, from)| (*c, from.iter().copied().sorted_by_key(|f| (f.y, f.x)).collect()))
+            .sorted_by_key(|(c, _)| (c.y, c.x))
+            .collect()
+    }
+}
+
+fn setup(input: &Input) -> (Paper, Vec<Fold>) {
+    (Paper::new(&input.0), input.1.clone())
+}
+
+fn fold(paper: &Paper, fold: &Fold) -> Paper {
+    // an axis fold keeps the fold line as the far edge of the near side; if
+    // the far side is the longer one it overhangs and becomes the new edge
+    let shift = match fold {
+        Fold::X(v) => Coord2D::new((paper.width - 1 - 2 * v).max(0), 0),
+        Fold::Y(v) => Coord2D::new(0, (paper.height - 1 - 2 * v).max(0)),
+        _ => Coord2D::new(0, 0),
+    };
+    let mut dots: HashMap<Coord2D, Vec<Coord2D>> = HashMap::new();
+    for (c, from) in paper.dots.iter() {
+        let (x, y) = (c.x, c.y);
+        let moved = match *fold {
+            Fold::X(v) if x > v => Coord2D::new(2 * v - x, y),
+            Fold::Y(v) if y > v => Coord2D::new(x, 2 * v - y),
+            Fold::Diag(v) if x - y > v => Coord2D::new(y + v, x - v),
+            Fold::AntiDiag(v) if x + y > v => Coord2D::new(v - y, v - x),
+            _ => *c,
+        };
+        dots.entry(moved + shift).or_default().extend(from);
+    }
+    let mut paper = Paper { dots, width: paper.width, height: paper.height };
+    match fold {
+        Fold::X(v) => paper.width = *v + shift.x,
+        Fold::Y(v) => paper.height = *v + shift.y,
+        _ => paper.fit(),
+    }
+    paper
+}
+
+// The 4x6 capital letters used by the puzzle's dot displays, as rows of
+// `#`/`.` read left to right, top to bottom.
+const FONT: &[(char, &str)] = &[
+    ('A', ".##.#..##..######..##..#"), ('B', "###.#..####.#..##..####."),
+    ('C', ".##.#..##...#...#..#.##."), ('E', "#####...###.#...#...####"),
+    ('F', "#####...###.#...#...#..."), ('G', ".##.#..##...#.###..#.###"),
+    ('H', "#..##..######..##..##..#"), ('I', ".###..#...#...#...#..###"),
+    ('J', "..##...#...#...##..#.##."), ('K', "#..##.#.##..#.#.#.#.#..#"),
+    ('L', "#...#...#...#...#...####"), ('O', ".##.#..##..##..##..#.##."),
+    ('P', "###.#..##..####.#...#..."), ('R', "###.#..##..####.#.#.#..#"),
+    ('S', ".####...#....##....####."), ('U', "#..##..##..##..##..#.##."),
+    ('Z', "####...#..#..#..#...####"),
+];
+const GLYPH_W: i64 = 4;
+const GLYPH_H: i64 = 6;
+const GLYPH_SPACING: i64 = 5;
+
+#[derive(Debug,Eq,PartialEq)]
+struct OcrError {
+    /// Indices (from the left, starting at 0) of glyphs that matched no letter.
+    unrecognized: Vec<usize>,
+}
+
+impl fmt::Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list: Vec<String> = self.unrecognized.iter().map(|p| p.to_string()).collect();
+        write!(f, "unrecognized glyphs at positions {}", list.join(", "))
     }
-    (grid, folds)
 }
 
-fn fold(grid: &InfiniteGrid<bool>, fold: &Fold) -> InfiniteGrid<bool> {
-    let mut newgrid = InfiniteGrid::new(false);
-    for ((x, y), c) in grid.iter() {
-        if !c { continue; }
-        match fold {
-            Fold::X(v) => {
-                if *x >= *v {
-                    newgrid.set(v - (*x - *v), *y, true);
-                } else {
-                    newgrid.set(*x, *y, true);
-                }
-            },
-            Fold::Y(v) => {
-                if *y >= *v {
-                    newgrid.set(*x, v - (*y - *v), true);
-                } else {
-                    newgrid.set(*x, *y, true);
-                }
-            },
+/// Reads the dots as a row of letters. Letter `i` occupies columns
+/// `5i..5i+4` and rows `0..6`; a dot in the gap between letters or below
+/// them makes the neighboring glyph unrecognizable.
+fn ocr(grid: &InfiniteGrid<bool>) -> Result<String, OcrError> {
+    let dots: Vec<(i64, i64)> = grid.iter().filter(|(_, c)| **c).map(|(p, _)| *p).collect();
+    let n_glyphs = dots.iter().map(|(x, _)| x / GLYPH_SPACING + 1).max().unwrap_or(0);
+    let mut text = String::new();
+    let mut unrecognized = Vec::new();
+    for i in 0..n_glyphs {
+        let x0 = i * GLYPH_SPACING;
+        let stray = dots.iter().any(|(x, y)|
+            (*x >= x0 && *x < x0 + GLYPH_SPACING) && (*x >= x0 + GLYPH_W || *y < 0 || *y >= GLYPH_H));
+        let glyph: String = (0..GLYPH_H)
+            .flat_map(|y| (x0..x0 + GLYPH_W).map(move |x| (x, y)))
+            .map(|(x, y)| if grid.get(x, y) { '#' } else { '.' })
+            .collect();
+        match FONT.iter().find(|(_, g)| *g == glyph) {
+            Some((c, _)) if !stray => text.push(*c),
+            _ => unrecognized.push(i as usize),
         }
     }
-    newgrid
+    if unrecognized.is_empty() && dots.iter().all(|(x, _)| *x >= 0) {
+        Ok(text)
+    } else {
+        Err(OcrError { unrecognized })
+    }
 }
 
 fn part1(input: &Input) -> usize {
-    let (grid, folds) = setup(input);
-    let grid = fold(&grid, &folds[0]);
-    //grid.print(|c| if c { '#' } else { '.' });
-    grid.iter().filter(|(_,c)| **c).count()
+    let (paper, folds) = setup(input);
+    fold(&paper, &folds[0]).dots.len()
 }
 
-fn part2(input: &Input) {
-    let (grid, folds) = setup(input);
-    let mut nextgrid = grid;
+fn fold_all(input: &Input) -> Paper {
+    let (paper, folds) = setup(input);
+    let mut nextpaper = paper;
     for f in folds {
-        nextgrid = fold(&nextgrid, &f);
+        nextpaper = fold(&nextpaper, &f);
     }
-    println!("Part 2:");
-    nextgrid.print(|c| if c { '#' } else { '.' });
+    nextpaper
+}
+
+fn part2(input: &Input) -> Result<String, OcrError> {
+    ocr(&fold_all(input).grid())
 }
 
 fn main() {
     let input: Input = read_sectioned_input();
     println!("Part 1: {}", part1(&input));
-    part2(&input);
+    match part2(&input) {
+        Ok(text) => println!("Part 2: {}", text),
+        Err(e) => {
+            println!("Part 2: {}", e);
+            fold_all(&input).grid().print(|c| if c { '#' } else { '.' });
+        },
+    }
+
+    // DAY13_PROVENANCE=1 also lists the input dots behind each final dot
+    if env::var_os("DAY13_PROVENANCE").is_some() {
+        for (c, from) in fold_all(&input).provenance() {
+            let from: Vec<String> = from.iter().map(|f| format!("{},{}", f.x, f.y)).collect();
+            println!("{},{} <- {}", c.x, c.y, from.join(" "));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -99,5 +233,67 @@ mod tests {
     fn day13_test() {
         let input: Input = sectioned_test_input(include_str!("day13.testinput"));
         assert_eq!(part1(&input), 17);
+        assert_eq!(part2(&input), Err(OcrError { unrecognized: vec![0] }));
+    }
+
+    fn render(text: &str) -> InfiniteGrid<bool> {
+        let mut grid = InfiniteGrid::new(false);
+        for (i, c) in text.chars().enumerate() {
+            let glyph = FONT.iter().find(|(g, _)| *g == c).unwrap().1;
+            for (n, _) in glyph.chars().enumerate().filter(|(_, d)| *d == '#') {
+                let n = n as i64;
+                grid.set(i as i64 * GLYPH_SPACING + n % GLYPH_W, n / GLYPH_W, true);
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn day13_ocr_test() {
+        let alphabet: String = FONT.iter().map(|(c, _)| *c).collect();
+        assert_eq!(ocr(&render(&alphabet)), Ok(alphabet));
+        assert_eq!(ocr(&render("JIL")), Ok("JIL".into()));
+        let mut grid = render("HELLO");
+        grid.set(6, 2, false);
+        grid.set(19, 0, true);
+        assert_eq!(ocr(&grid), Err(OcrError { unrecognized: vec![1, 3] }));
+        assert_eq!(ocr(&grid).unwrap_err().to_string(), "unrecognized glyphs at positions 1, 3");
+    }
+
+    #[test]
+    fn day13_fold_test() {
+        assert_eq!("fold along x-y=3".parse::<Fold>(), Ok(Fold::Diag(3)));
+        assert_eq!("fold along x+y=10".parse::<Fold>(), Ok(Fold::AntiDiag(10)));
+        assert_eq!("fold along y=7".parse::<Fold>(), Ok(Fold::Y(7)));
+
+        let input: Input = sectioned_test_input(include_str!("day13.testinput"));
+        let paper = fold_all(&input);
+        let prov = paper.provenance();
+        assert_eq!(prov.len(), 16);
+        assert_eq!(prov.iter().map(|(_, from)| from.len()).sum::<usize>(), input.0.len());
+        assert_eq!(prov[0], (Coord2D::new(0, 0), vec![Coord2D::new(0, 14)]));
+
+        // far side longer than the near side: the overhang becomes the new edge
+        let paper = Paper::new(&[Coord2D::new(0, 0), Coord2D::new(9, 0), Coord2D::new(3, 1)]);
+        let folded = fold(&paper, &Fold::X(2));
+        assert_eq!(folded.width, 7);
+        assert_eq!(folded.provenance(), vec![
+            (Coord2D::new(0, 0), vec![Coord2D::new(9, 0)]),
+            (Coord2D::new(5, 0), vec![Coord2D::new(0, 0)]),
+            (Coord2D::new(6, 1), vec![Coord2D::new(3, 1)]),
+        ]);
+
+        let paper = Paper::new(&[Coord2D::new(4, 0), Coord2D::new(0, 4), Coord2D::new(3, 3)]);
+        let folded = fold(&paper, &Fold::Diag(0));
+        assert_eq!(folded.provenance(), vec![
+            (Coord2D::new(3, 3), vec![Coord2D::new(3, 3)]),
+            (Coord2D::new(0, 4), vec![Coord2D::new(4, 0), Coord2D::new(0, 4)]),
+        ]);
+        let folded = fold(&paper, &Fold::AntiDiag(4));
+        assert_eq!(folded.provenance(), vec![
+            (Coord2D::new(4, 0), vec![Coord2D::new(4, 0)]),
+            (Coord2D::new(1, 1), vec![Coord2D::new(3, 3)]),
+            (Coord2D::new(0, 4), vec![Coord2D::new(0, 4)]),
+        ]);
     }
 }
@@ -1,13 +1,21 @@
 use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Display};
 use std::str::FromStr;
 use std::vec::Vec;
 use itertools::Itertools;
 use itertools::MinMaxResult::MinMax;
+use num::{BigUint, Integer, One, Zero};
+use rand::Rng;
 use ya_advent_lib::read::read_input;
 
+/// One possible insertion for a pair, with its relative weight. An empty
+/// insertion leaves the pair as it is.
+type Insertion = (String, u64);
+
 enum Input {
     Template(String),
-    Rule((char, char), char),
+    Rule((char, char), Vec<Insertion>),
     Blank,
 }
 
@@ -16,13 +24,23 @@ impl FromStr for Input {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.is_empty() {
             Ok(Input::Blank)
-        } else if s.contains(" -> ") {
-            let (one, two) = s.split_once(" -> ").unwrap();
+        } else if s.contains(" ->") {
+            let (one, two) = s.split_once(" ->").unwrap();
             let mut cc = one.chars();
-            Ok(Input::Rule(
-                (cc.next().unwrap(), cc.next().unwrap()),
-                two.chars().next().unwrap(),
-            ))
+            let pair = (cc.next().ok_or(())?, cc.next().ok_or(())?);
+            let options = two
+                .split('|')
+                .map(|opt| match opt.trim().split_once(':') {
+                    // a zero weight could never be chosen, and would leave a
+                    // zero total to divide by if it stood alone
+                    Some((ins, w)) => match w.parse() {
+                        Ok(0) | Err(_) => Err(()),
+                        Ok(w) => Ok((ins.into(), w)),
+                    },
+                    None => Ok((opt.trim().into(), 1)),
+                })
+                .collect::<Result<Vec<Insertion>, ()>>()?;
+            Ok(Input::Rule(pair, options))
         }
         else {
             Ok(Input::Template(s.into()))
@@ -30,95 +48,359 @@ impl FromStr for Input {
     }
 }
 
-fn setup(input: &[Input]) -> (String, HashMap<(char, char), char>) {
+type Rules = HashMap<(char, char), Vec<Insertion>>;
+
+fn setup(input: &[Input]) -> (String, Rules) {
     let mut template = String::new();
     let mut rules = HashMap::new();
     for i in input {
         match i {
             Input::Blank => {},
             Input::Template(s) => { template = s.clone(); },
-            Input::Rule(k, v) => { rules.insert(*k, *v); },
+            Input::Rule(k, v) => { rules.insert(*k, v.clone()); },
         }
     }
     (template, rules)
 }
 
-fn run(input: &[Input], iters: i64) -> i64 {
+/// The pairs a pair turns into when `ins` is inserted between its elements.
+fn split_pair(p: (char, char), ins: &str) -> Vec<(char, char)> {
+    std::iter::once(p.0)
+        .chain(ins.chars())
+        .chain(std::iter::once(p.1))
+        .tuple_windows()
+        .collect()
+}
+
+/// Builds the polymer itself, which is only practical for small step
+/// counts. Where a rule has several options one is picked at random by weight.
+fn polymer<R: Rng>(input: &[Input], steps: usize, rng: &mut R) -> String {
     let (template, rules) = setup(input);
-    let mut pairs: HashMap<(char, char), i64> = HashMap::new();
-    let mut histogram: HashMap<char, i64> = HashMap::new();
-    template
+    let mut polymer = template;
+    for _ in 0..steps {
+        let mut next = String::with_capacity(polymer.len() * 2);
+        next.extend(polymer.chars().next());
+        for p in polymer.chars().tuple_windows() {
+            if let Some(options) = rules.get(&p) {
+                let total: u64 = options.iter().map(|(_, w)| w).sum();
+                let mut pick = rng.gen_range(0..total);
+                let (ins, _) = options.iter().find(|(_, w)| {
+                    let hit = pick < *w;
+                    pick = pick.saturating_sub(*w);
+                    hit
+                }).unwrap();
+                next.push_str(ins);
+            }
+            next.push(p.1);
+        }
+        polymer = next;
+    }
+    polymer
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum CountError {
+    /// A rule's weights add up to a total sharing a factor with the modulus,
+    /// so the total has no inverse to divide by.
+    NoInverse { total: u64, modulus: u64 },
+}
+
+impl fmt::Display for CountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CountError::NoInverse { total, modulus } =>
+                write!(f, "rule weights total {}, which has no inverse modulo {}", total, modulus),
+        }
+    }
+}
+
+/// The arithmetic used for counting: exact, modulo some number, or
+/// floating-point expected values.
+trait Counting {
+    type T: Clone + PartialEq;
+    fn zero(&self) -> Self::T;
+    fn one(&self) -> Self::T;
+    /// The probability `num / den` of a weighted option.
+    fn fraction(&self, num: u64, den: u64) -> Result<Self::T, CountError>;
+    fn add(&self, a: &Self::T, b: &Self::T) -> Self::T;
+    fn mul(&self, a: &Self::T, b: &Self::T) -> Self::T;
+}
+
+struct Exact;
+
+impl Counting for Exact {
+    type T = BigUint;
+    fn zero(&self) -> BigUint { BigUint::zero() }
+    fn one(&self) -> BigUint { BigUint::one() }
+    fn fraction(&self, num: u64, den: u64) -> Result<BigUint, CountError> {
+        assert!(num == den, "weighted rules can't be counted exactly");
+        Ok(BigUint::one())
+    }
+    fn add(&self, a: &BigUint, b: &BigUint) -> BigUint { a + b }
+    fn mul(&self, a: &BigUint, b: &BigUint) -> BigUint { a * b }
+}
+
+/// Counts modulo some number. Weighted rules only work if each rule's
+/// weight total is coprime to it, which is always so for a large prime.
+struct Modulo(u64);
+
+impl Counting for Modulo {
+    type T = u64;
+    fn zero(&self) -> u64 { 0 }
+    fn one(&self) -> u64 { 1 % self.0 }
+    fn fraction(&self, num: u64, den: u64) -> Result<u64, CountError> {
+        // x * den + y * m = gcd(den, m), so x is the inverse of den when the
+        // gcd is 1
+        let e = (den as i128).extended_gcd(&(self.0 as i128));
+        if e.gcd != 1 {
+            return Err(CountError::NoInverse { total: den, modulus: self.0 });
+        }
+        let inv = e.x.rem_euclid(self.0 as i128) as u64;
+        Ok(self.mul(&(num % self.0), &inv))
+    }
+    fn add(&self, a: &u64, b: &u64) -> u64 { ((*a as u128 + *b as u128) % self.0 as u128) as u64 }
+    fn mul(&self, a: &u64, b: &u64) -> u64 { ((*a as u128 * *b as u128) % self.0 as u128) as u64 }
+}
+
+struct Expected;
+
+impl Counting for Expected {
+    type T = f64;
+    fn zero(&self) -> f64 { 0.0 }
+    fn one(&self) -> f64 { 1.0 }
+    fn fraction(&self, num: u64, den: u64) -> Result<f64, CountError> { Ok(num as f64 / den as f64) }
+    fn add(&self, a: &f64, b: &f64) -> f64 { a + b }
+    fn mul(&self, a: &f64, b: &f64) -> f64 { a * b }
+}
+
+type Matrix<T> = Vec<Vec<T>>;
+
+fn mat_mul<C: Counting>(c: &C, a: &Matrix<C::T>, b: &Matrix<C::T>) -> Matrix<C::T> {
+    let n = a.len();
+    let zero = c.zero();
+    let mut out = vec![vec![c.zero(); n]; n];
+    for i in 0..n {
+        for k in 0..n {
+            if a[i][k] == zero {
+                continue;
+            }
+            for j in 0..n {
+                if b[k][j] != zero {
+                    out[i][j] = c.add(&out[i][j], &c.mul(&a[i][k], &b[k][j]));
+                }
+            }
+        }
+    }
+    out
+}
+
+fn vec_mul<C: Counting>(c: &C, v: &[C::T], m: &Matrix<C::T>) -> Vec<C::T> {
+    (0..m.len())
+        .map(|j| v.iter()
+            .zip(m.iter())
+            .fold(c.zero(), |acc, (x, row)| c.add(&acc, &c.mul(x, &row[j]))))
+        .collect()
+}
+
+/// Element counts after `steps` insertion steps. The pair counts evolve by
+/// a fixed linear map, so they're found by raising its matrix to the power
+/// `steps` by repeated squaring. Exact counts roughly double in size every
+/// step, so very large step counts need `Modulo`.
+fn histogram<C: Counting>(input: &[Input], steps: u64, c: &C) -> Result<HashMap<char, C::T>, CountError> {
+    let (template, rules) = setup(input);
+    let elements: Vec<char> = template
         .chars()
-        .map(|c| {
-            histogram.entry(c)
-                .and_modify(|count| *count += 1)
-                .or_insert(1);
-            c
-        })
-        .tuple_windows()
-        .for_each(|w| {
-            pairs
-                .entry(w)
-                .and_modify(|count| *count += 1)
-                .or_insert(1);
-        });
-    for _ in 0..iters {
-        let mut newpairs: HashMap<(char, char), i64> = HashMap::new();
-        for (p, count) in &pairs {
-            let c = rules[&p];
-            histogram
-                .entry(c)
-                .and_modify(|x| *x += count)
-                .or_insert(*count);
-            newpairs
-                .entry((p.0, c))
-                .and_modify(|x| *x += count)
-                .or_insert(*count);
-            newpairs
-                .entry((c, p.1))
-                .and_modify(|x| *x += count)
-                .or_insert(*count);
-            newpairs
-                .entry(*p)
-                .and_modify(|x| *x -= count)
-                .or_insert(-*count);
+        .chain(rules.iter().flat_map(|((a, b), v)| [*a, *b].into_iter().chain(v.iter().flat_map(|(s, _)| s.chars()))))
+        .unique()
+        .sorted()
+        .collect();
+    let pairs: Vec<(char, char)> = elements.iter().cartesian_product(elements.iter()).map(|(a, b)| (*a, *b)).collect();
+    let index: HashMap<(char, char), usize> = pairs.iter().enumerate().map(|(i, p)| (*p, i)).collect();
+    let one = c.one();
+
+    let mut matrix = vec![vec![c.zero(); pairs.len()]; pairs.len()];
+    for (i, p) in pairs.iter().enumerate() {
+        // a pair without a rule just stays put
+        let stay = vec![(String::new(), 1)];
+        let options = rules.get(p).unwrap_or(&stay);
+        let total: u64 = options.iter().map(|(_, w)| w).sum();
+        for (ins, w) in options {
+            let share = c.fraction(*w, total)?;
+            for q in split_pair(*p, ins) {
+                matrix[i][index[&q]] = c.add(&matrix[i][index[&q]], &share);
+            }
         }
-        for (p, count) in newpairs {
-            pairs
-                .entry(p)
-                .and_modify(|x| *x += count)
-                .or_insert(count);
+    }
+    let mut counts = vec![c.zero(); pairs.len()];
+    for p in template.chars().tuple_windows() {
+        let i = index[&p];
+        counts[i] = c.add(&counts[i], &one);
+    }
+    let mut n = steps;
+    while n > 0 {
+        if n & 1 == 1 {
+            counts = vec_mul(c, &counts, &matrix);
+        }
+        n >>= 1;
+        if n > 0 {
+            matrix = mat_mul(c, &matrix, &matrix);
         }
     }
-    match histogram.values().minmax() {
-        MinMax(min, max) => *max - *min,
+
+    // every element but the last is the first half of exactly one pair
+    let mut histogram: HashMap<char, C::T> = HashMap::new();
+    for (p, n) in pairs.iter().zip(counts.iter()) {
+        let e = histogram.entry(p.0).or_insert_with(|| c.zero());
+        *e = c.add(e, n);
+    }
+    if let Some(last) = template.chars().last() {
+        let e = histogram.entry(last).or_insert_with(|| c.zero());
+        *e = c.add(e, &one);
+    }
+    histogram.retain(|_, n| *n != c.zero());
+    Ok(histogram)
+}
+
+fn run(input: &[Input], iters: u64) -> BigUint {
+    match histogram(input, iters, &Exact).unwrap().values().minmax() {
+        MinMax(min, max) => max - min,
         _ => panic!(),
     }
 }
 
-fn part1(input: &[Input]) -> i64 {
+fn part1(input: &[Input]) -> BigUint {
     run(input, 10)
 }
 
-fn part2(input: &[Input]) -> i64 {
+fn part2(input: &[Input]) -> BigUint {
     run(input, 40)
 }
 
+fn print_counts<T: Display>(histogram: Result<HashMap<char, T>, CountError>) {
+    let histogram = match histogram {
+        Ok(h) => h,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+    for (e, n) in histogram.iter().sorted_by_key(|(e, _)| **e) {
+        println!("{}: {}", e, n);
+    }
+}
+
 fn main() {
     let input: Vec<Input> = read_input();
-    println!("Part 1: {}", part1(&input));
-    println!("Part 2: {}", part2(&input));
+    let weighted = setup(&input).1.values().any(|options| options.len() > 1);
+    if weighted {
+        println!("Weighted rules have no exact counts");
+    } else {
+        println!("Part 1: {}", part1(&input));
+        println!("Part 2: {}", part2(&input));
+    }
+
+    // DAY14_STEPS=<n> also prints each element's count after n steps, taken
+    // modulo DAY14_MODULUS=<m> if that is set; with DAY14_EXPECTED set,
+    // counts are averaged over the choices of weighted rules instead
+    if let Ok(steps) = env::var("DAY14_STEPS") {
+        let Ok(steps) = steps.parse() else {
+            eprintln!("DAY14_STEPS must be a number of steps");
+            std::process::exit(1);
+        };
+        match env::var("DAY14_MODULUS").ok().map(|m| m.parse::<u64>()) {
+            _ if env::var_os("DAY14_EXPECTED").is_some() => print_counts(histogram(&input, steps, &Expected)),
+            None if !weighted => print_counts(histogram(&input, steps, &Exact)),
+            Some(Ok(p)) if p >= 2 => print_counts(histogram(&input, steps, &Modulo(p))),
+            None => {
+                eprintln!("Weighted rules need DAY14_MODULUS or DAY14_EXPECTED");
+                std::process::exit(1);
+            },
+            Some(_) => {
+                eprintln!("DAY14_MODULUS must be at least 2");
+                std::process::exit(1);
+            },
+        }
+    }
+
+    // DAY14_POLYMER=<n> also grows the polymer itself for n steps
+    if let Ok(steps) = env::var("DAY14_POLYMER") {
+        let Ok(steps) = steps.parse() else {
+            eprintln!("DAY14_POLYMER must be a number of steps");
+            std::process::exit(1);
+        };
+        println!("{}", polymer(&input, steps, &mut rand::thread_rng()));
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
     use ya_advent_lib::read::test_input;
     use super::*;
 
     #[test]
     fn day14_test() {
         let input: Vec<Input> = test_input(include_str!("day14.testinput"));
-        assert_eq!(part1(&input), 1588);
-        assert_eq!(part2(&input), 2188189693529);
+        assert_eq!(part1(&input), 1588u32.into());
+        assert_eq!(part2(&input), 2188189693529u64.into());
+    }
+
+    #[test]
+    fn day14_matrix_test() {
+        let input: Vec<Input> = test_input(include_str!("day14.testinput"));
+        let exact = histogram(&input, 10, &Exact).unwrap();
+        assert_eq!(exact[&'B'], 1749u32.into());
+        assert_eq!(exact[&'H'], 161u32.into());
+        assert_eq!(histogram(&input, 0, &Exact).unwrap()[&'N'], 2u32.into());
+        // the polymer has 3 * 2^n + 1 elements after n steps
+        let total: BigUint = histogram(&input, 100, &Exact).unwrap().values().sum();
+        assert_eq!(total, BigUint::from(3u32) * BigUint::from(2u32).pow(100) + 1u32);
+
+        let p = 1_000_000_007;
+        let modular = histogram(&input, 40, &Modulo(p)).unwrap();
+        for (e, n) in histogram(&input, 40, &Exact).unwrap() {
+            assert_eq!(BigUint::from(modular[&e]), n % p);
+        }
+        let big = histogram(&input, 1_000_000_000_000, &Modulo(p)).unwrap();
+        let total = big.values().fold(0, |acc, n| (acc + n) % p);
+        let pow2 = BigUint::from(2u32).modpow(&BigUint::from(1_000_000_000_000u64), &BigUint::from(p));
+        assert_eq!(BigUint::from(total), (pow2 * 3u32 + 1u32) % p);
+    }
+
+    #[test]
+    fn day14_grammar_test() {
+        let input: Vec<Input> = test_input(include_str!("day14.testinput"));
+        let mut rng = StdRng::seed_from_u64(14);
+        assert_eq!(polymer(&input, 2, &mut rng), "NBCCNBBBCBHCB");
+        let long = polymer(&input, 10, &mut rng);
+        let exact = histogram(&input, 10, &Exact).unwrap();
+        for (e, n) in exact {
+            assert_eq!(BigUint::from(long.chars().filter(|c| *c == e).count()), n);
+        }
+
+        let input: Vec<Input> = test_input("ABC\n\nAB -> XY\nXY -> \nYB -> Z");
+        assert_eq!(polymer(&input, 1, &mut rng), "AXYBC");
+        assert_eq!(polymer(&input, 2, &mut rng), "AXYZBC");
+        assert_eq!(polymer(&input, 3, &mut rng), "AXYZBC");
+        let h = histogram(&input, 3, &Exact).unwrap();
+        assert_eq!(h.values().sum::<BigUint>(), 6u32.into());
+        assert_eq!(h[&'Z'], 1u32.into());
+
+        let input: Vec<Input> = test_input("AB\n\nAB -> A:1 | B:3\nAA -> B\nBB -> A\nBA -> A:1 | B:1");
+        let e = histogram(&input, 1, &Expected).unwrap();
+        assert_eq!((e[&'A'], e[&'B']), (1.25, 1.75));
+        let e = histogram(&input, 20, &Expected).unwrap();
+        assert!((e.values().sum::<f64>() - ((1 << 20) + 1) as f64).abs() < 1e-6);
+        let p = 1_000_000_007;
+        let m = histogram(&input, 1, &Modulo(p)).unwrap();
+        assert_eq!(m[&'A'] * 4 % p, 5);
+        let m = histogram(&input, 1, &Modulo(15)).unwrap();
+        assert_eq!(m[&'A'] * 4 % 15, 5);
+        assert!(matches!(histogram(&input, 1, &Modulo(6)), Err(CountError::NoInverse { modulus: 6, .. })));
+        assert!("AB -> A:0".parse::<Input>().is_err());
+        assert!("AB -> A:0 | B:1".parse::<Input>().is_err());
+        let sample = polymer(&input, 12, &mut rng);
+        assert_eq!(sample.len(), (1 << 12) + 1);
     }
 }
@@ -1,6 +1,11 @@
-use std::cmp::Reverse;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::HashSet;
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::str::FromStr;
 use std::vec::Vec;
+use advent2021::search::{a_star, dijkstra, Path};
+use ya_advent_lib::coords::Coord2D;
 use ya_advent_lib::grid::Grid;
 use ya_advent_lib::read::read_input;
 
@@ -11,54 +16,150 @@ fn mkgrid(input: &[String]) -> Grid<u8> {
     })
 }
 
-fn up(loc: (i64, i64)) -> (i64, i64) { (loc.0, loc.1 - 1) }
-fn down(loc: (i64, i64)) -> (i64, i64) { (loc.0, loc.1 + 1) }
-fn left(loc: (i64, i64)) -> (i64, i64) { (loc.0 - 1, loc.1) }
-fn right(loc: (i64, i64)) -> (i64, i64) { (loc.0 + 1, loc.1) }
+/// The puzzle's rule: each tile right or down adds one, wrapping 9 to 1.
+fn wrap_increment(risk: u8, tx: i64, ty: i64) -> u8 {
+    ((risk as i64 - 1 + tx + ty) % 9 + 1) as u8
+}
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+enum Moves {
+    Orthogonal,
+    /// Diagonal steps only, each costing this much on top of the risk entered.
+    Diagonal(usize),
+    /// Orthogonal and diagonal steps; diagonal ones cost this much extra.
+    King(usize),
+}
+
+/// `orthogonal`, `diagonal:<extra>` or `king:<extra>`.
+impl FromStr for Moves {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, extra) = s.split_once(':').unwrap_or((s, "0"));
+        let extra = extra.parse().map_err(|_| ())?;
+        match kind {
+            "orthogonal" if extra == 0 => Ok(Moves::Orthogonal),
+            "diagonal" => Ok(Moves::Diagonal(extra)),
+            "king" => Ok(Moves::King(extra)),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone,Copy)]
+struct Rules {
+    tiles_x: i64,
+    tiles_y: i64,
+    /// Risk of a cell given its base risk and which tile it is in.
+    increment: fn(u8, i64, i64) -> u8,
+    moves: Moves,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules { tiles_x: 1, tiles_y: 1, increment: wrap_increment, moves: Moves::Orthogonal }
+    }
+}
+
+struct Cave<'a> {
+    grid: &'a Grid<u8>,
+    rules: Rules,
+}
+
+impl<'a> Cave<'a> {
+    fn new(grid: &'a Grid<u8>, rules: Rules) -> Self {
+        Self { grid, rules }
+    }
+
+    fn tile_size(&self) -> (i64, i64) {
+        (self.grid.x_bounds().end, self.grid.y_bounds().end)
+    }
+
+    fn width(&self) -> i64 {
+        self.tile_size().0 * self.rules.tiles_x
+    }
+
+    fn height(&self) -> i64 {
+        self.tile_size().1 * self.rules.tiles_y
+    }
 
-fn traverse<F>(getter: F, width: i64, height: i64) -> usize
-        where F: Fn((i64, i64)) -> u8 {
-    let target = ( width - 1, height - 1 );
-    let h = |(x,y)| ((target.0 - x) + (target.1 - y)) as usize;
+    fn risk(&self, c: Coord2D) -> u8 {
+        let (w, h) = self.tile_size();
+        (self.rules.increment)(self.grid.get(c.x % w, c.y % h), c.x / w, c.y / h)
+    }
 
-    let mut queue: BinaryHeap<(Reverse<usize>,(i64,i64))> = BinaryHeap::new();
-    queue.push((Reverse(h((0, 0))), (0, 0)));
-    let mut traversed: HashMap<(i64,i64),usize> = HashMap::new();
-    traversed.insert((0, 0), 0);
+    fn neighbors(&self, c: Coord2D) -> Vec<(Coord2D, usize)> {
+        let (ortho, diag) = match self.rules.moves {
+            Moves::Orthogonal => (true, None),
+            Moves::Diagonal(extra) => (false, Some(extra)),
+            Moves::King(extra) => (true, Some(extra)),
+        };
+        let mut steps: Vec<(Coord2D, usize)> = Vec::new();
+        if ortho {
+            steps.extend(c.neighbors4().into_iter().map(|n| (n, 0)));
+        }
+        if let Some(extra) = diag {
+            steps.extend([(1, 1), (1, -1), (-1, 1), (-1, -1)].into_iter().map(|d| (c + d, extra)));
+        }
+        steps
+            .into_iter()
+            .filter(|(n, _)| n.x >= 0 && n.x < self.width() && n.y >= 0 && n.y < self.height())
+            .map(|(n, extra)| (n, self.risk(n) as usize + extra))
+            .collect()
+    }
 
-    while let Some((_, loc)) = queue.pop() {
-        if loc == target {
-            return traversed[&loc];
+    /// The least risky path from the top left to the bottom right, if the
+    /// moves allowed can get there at all.
+    fn traverse(&self) -> Option<Path<Coord2D>> {
+        let target = Coord2D::new(self.width() - 1, self.height() - 1);
+        let start = Coord2D::new(0, 0);
+        // every step costs at least the lowest risk on the map, plus the
+        // diagonal surcharge when every step is diagonal
+        let min_risk = (0..self.height())
+            .flat_map(|y| (0..self.width()).map(move |x| Coord2D::new(x, y)))
+            .map(|c| self.risk(c) as usize)
+            .min()
+            .unwrap_or(0);
+        let min_step = match self.rules.moves {
+            Moves::Diagonal(extra) => min_risk + extra,
+            _ => min_risk,
+        };
+        if min_step == 0 {
+            return dijkstra(start, |c| *c == target, |c| self.neighbors(*c));
         }
-        let cur_risk = traversed[&(loc.0, loc.1)];
-        for p in [up(loc), down(loc), left(loc), right(loc)] {
-            if p.0 >= 0 && p.0 < width
-                && p.1 >= 0 && p.1 < height
-            {
-                let d = getter(p) as usize + cur_risk;
-                if !traversed.contains_key(&p) || d < traversed[&p] {
-                    traversed.insert(p, d);
-                    queue.push((Reverse(d + h(p)), p));
-                }
+        let h = |c: &Coord2D| min_step * match self.rules.moves {
+            Moves::Orthogonal => (target.x - c.x) + (target.y - c.y),
+            _ => (target.x - c.x).max(target.y - c.y),
+        } as usize;
+        a_star(start, |c| *c == target, |c| self.neighbors(*c), h)
+    }
+
+    /// Renders the risk levels in grey, darker being riskier, with the
+    /// path drawn over them in red.
+    fn write_ppm(&self, path: &Path<Coord2D>, out: &mut dyn Write) -> io::Result<()> {
+        let on_path: HashSet<Coord2D> = path.nodes.iter().copied().collect();
+        write!(out, "P6\n{} {}\n255\n", self.width(), self.height())?;
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let c = Coord2D::new(x, y);
+                let pixel = if on_path.contains(&c) {
+                    [255, 0, 0]
+                } else {
+                    [255 - self.risk(c).min(9) * 25; 3]
+                };
+                out.write_all(&pixel)?;
             }
         }
+        Ok(())
     }
-    panic!();
 }
 
 fn part1(grid: &Grid<u8>) -> usize {
-    traverse(|(x, y)| grid.get(x, y), grid.x_bounds().end, grid.y_bounds().end)
+    Cave::new(grid, Rules::default()).traverse().unwrap().cost
 }
 
 fn part2(grid: &Grid<u8>) -> usize {
-    let width = grid.x_bounds().end;
-    let height = grid.y_bounds().end;
-    traverse(|(x, y)| {
-        let v = grid.get(x % width, y % width) - 1;
-        let xs = (x / width) as u8;
-        let ys = (y / width) as u8;
-        ((v + xs + ys) % 9) + 1
-    }, width * 5, height * 5)
+    let rules = Rules { tiles_x: 5, tiles_y: 5, ..Rules::default() };
+    Cave::new(grid, rules).traverse().unwrap().cost
 }
 
 fn main() {
@@ -67,6 +168,29 @@ fn main() {
 
     println!("Part 1: {}", part1(&grid));
     println!("Part 2: {}", part2(&grid));
+
+    // DAY15_MOVES=<moves> also solves the full map with another move set,
+    // and DAY15_PPM=<file> writes the path taken as an image
+    let moves = env::var("DAY15_MOVES").ok();
+    let ppm = env::var("DAY15_PPM").ok();
+    if moves.is_some() || ppm.is_some() {
+        let Ok(moves) = moves.as_deref().unwrap_or("orthogonal").parse() else {
+            eprintln!("DAY15_MOVES must be orthogonal, diagonal:<extra> or king:<extra>");
+            std::process::exit(1);
+        };
+        let cave = Cave::new(&grid, Rules { tiles_x: 5, tiles_y: 5, moves, ..Rules::default() });
+        let Some(path) = cave.traverse() else {
+            println!("No path with {:?} moves", moves);
+            return;
+        };
+        println!("{:?} moves: {}", moves, path.cost);
+        if let Some(ppm) = ppm {
+            if let Err(e) = File::create(&ppm).and_then(|mut f| cave.write_ppm(&path, &mut f)) {
+                eprintln!("{}: {}", ppm, e);
+                std::process::exit(1);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -81,4 +205,60 @@ mod tests {
         assert_eq!(part1(&grid), 40);
         assert_eq!(part2(&grid), 315);
     }
+
+    #[test]
+    fn day15_path_test() {
+        let input: Vec<String> = test_input(include_str!("day15.testinput"));
+        let grid = mkgrid(&input);
+        let cave = Cave::new(&grid, Rules::default());
+        let path = cave.traverse().unwrap();
+        assert_eq!(path.nodes.first(), Some(&Coord2D::new(0, 0)));
+        assert_eq!(path.nodes.last(), Some(&Coord2D::new(9, 9)));
+        assert_eq!(path.nodes.len(), 19);
+        let risk: usize = path.nodes[1..].iter().map(|c| grid.get_c(*c) as usize).sum();
+        assert_eq!(risk, path.cost);
+        let mut ppm: Vec<u8> = Vec::new();
+        cave.write_ppm(&path, &mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n10 10\n255\n"));
+        assert_eq!(ppm.len(), 13 + 10 * 10 * 3);
+        assert_eq!(ppm[13..].chunks(3).filter(|p| *p == [255, 0, 0]).count(), 19);
+    }
+
+    #[test]
+    fn day15_rules_test() {
+        let input: Vec<String> = test_input("19\n11\n91");
+        let grid = mkgrid(&input);
+        let cave = Cave::new(&grid, Rules { tiles_x: 3, tiles_y: 1, ..Rules::default() });
+        assert_eq!((cave.width(), cave.height()), (6, 3));
+        assert_eq!(cave.risk(Coord2D::new(5, 2)), 3);
+        assert_eq!(cave.traverse().unwrap().cost, 1 + 1 + 1 + 1 + 2 + 2 + 3);
+        let flat = Cave::new(&grid, Rules { tiles_x: 2, tiles_y: 3, increment: |r, _, _| r, ..Rules::default() });
+        assert_eq!((flat.width(), flat.height()), (4, 9));
+        assert_eq!(flat.risk(Coord2D::new(3, 8)), 1);
+
+        assert_eq!("king:2".parse(), Ok(Moves::King(2)));
+        assert_eq!("diagonal".parse(), Ok(Moves::Diagonal(0)));
+        assert_eq!("orthogonal:1".parse::<Moves>(), Err(()));
+        let king = Cave::new(&grid, Rules { moves: Moves::King(0), ..Rules::default() });
+        assert_eq!(king.traverse().unwrap().nodes.len(), 3);
+        assert_eq!(king.traverse().unwrap().cost, 2);
+        let king = Cave::new(&grid, Rules { moves: Moves::King(2), ..Rules::default() });
+        assert_eq!(king.traverse().unwrap().cost, 3);
+        // diagonal steps alone can only reach cells of the same colour
+        let diag = Cave::new(&grid, Rules { moves: Moves::Diagonal(0), ..Rules::default() });
+        assert_eq!(diag.traverse(), None);
+        let input: Vec<String> = test_input("191\n111\n911");
+        let grid = mkgrid(&input);
+        let diag = Cave::new(&grid, Rules { moves: Moves::Diagonal(1), ..Rules::default() });
+        assert_eq!(diag.traverse().unwrap().cost, 4);
+        let diag = Cave::new(&grid, Rules { moves: Moves::Diagonal(0), ..Rules::default() });
+        assert_eq!(diag.traverse().unwrap().nodes.len(), 3);
+
+        // zero-risk cells make steps cheaper than a unit-cost heuristic assumes
+        let grid = mkgrid(&test_input("4709\n0400\n0320\n0800"));
+        let cave = Cave::new(&grid, Rules { increment: |r, _, _| r, ..Rules::default() });
+        let target = Coord2D::new(3, 3);
+        let d = dijkstra(Coord2D::new(0, 0), |c| *c == target, |c| cave.neighbors(*c)).unwrap();
+        assert_eq!((cave.traverse().unwrap().cost, d.cost), (4, 4));
+    }
 }
@@ -1,97 +1,305 @@
-use std::collections::HashMap;
+use std::env;
+use std::fmt;
 use std::vec::Vec;
-use lazy_static::lazy_static;
 use ya_advent_lib::read::read_input;
 
-lazy_static! {
-    static ref HEX2BIN: HashMap<char, &'static str> = [
-        ('0', "0000"),
-        ('1', "0001"),
-        ('2', "0010"),
-        ('3', "0011"),
-        ('4', "0100"),
-        ('5', "0101"),
-        ('6', "0110"),
-        ('7', "0111"),
-        ('8', "1000"),
-        ('9', "1001"),
-        ('A', "1010"),
-        ('B', "1011"),
-        ('C', "1100"),
-        ('D', "1101"),
-        ('E', "1110"),
-        ('F', "1111"),
-    ].iter().cloned().collect();
+/// How an operator packet says where its subpackets end.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum LengthType {
+    /// Type 0: the total length of the subpackets in bits.
+    Bits,
+    /// Type 1: the number of subpackets.
+    Count,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 enum Payload {
     Literal(u64),
-    Operator(Vec<Packet>),
+    Operator(LengthType, Vec<Packet>),
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 struct Packet {
     version: u8,
     type_id: u8,
     payload: Payload,
 }
 
-fn bitstream(hex: &str) -> impl Iterator<Item=char> + '_ {
-    hex
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PacketError {
+    /// Something other than an uppercase hex digit, at this 1-based column.
+    BadDigit { col: usize, found: char },
+    /// The packet starting at this bit offset runs past the end of the
+    /// transmission, or of the bits its parent gave its subpackets.
+    Truncated { at: usize },
+    /// A comparison packet at this bit offset without exactly two subpackets.
+    BadComparison { at: usize, count: usize },
+    /// A minimum or maximum packet at this bit offset with no subpackets.
+    EmptyMinMax { at: usize },
+    /// A literal at this bit offset with a value too large for 64 bits.
+    LiteralOverflow { at: usize },
+    /// The packet at this bit offset is nested more than `MAX_DEPTH`
+    /// operators deep.
+    TooDeep { at: usize },
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PacketError::BadDigit { col, found } => write!(f, "bad hex digit {:?} at column {}", found, col),
+            PacketError::Truncated { at } => write!(f, "packet at bit {} is truncated", at),
+            PacketError::BadComparison { at, count } =>
+                write!(f, "comparison at bit {} has {} subpackets instead of 2", at, count),
+            PacketError::EmptyMinMax { at } => write!(f, "minimum or maximum at bit {} has no subpackets", at),
+            PacketError::LiteralOverflow { at } => write!(f, "literal at bit {} doesn't fit in 64 bits", at),
+            PacketError::TooDeep { at } => write!(f, "packet at bit {} is nested too deeply", at),
+        }
+    }
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, PacketError> {
+    let nibbles = hex
         .chars()
-        .flat_map(|c| HEX2BIN[&c].chars())
+        .enumerate()
+        .map(|(i, c)| match c {
+            '0'..='9' | 'A'..='F' => Ok(c.to_digit(16).unwrap() as u8),
+            _ => Err(PacketError::BadDigit { col: i + 1, found: c }),
+        })
+        .collect::<Result<Vec<u8>, PacketError>>()?;
+    Ok(nibbles
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0))
+        .collect())
+}
+
+/// Reads bits, most significant first, from `pos` up to `end`.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    end: usize,
 }
 
-fn get_bits_to_int(n: usize, bitstream: &mut dyn Iterator<Item=char>) -> u64 {
-    assert!(n <= 64);
-    let bitstr: String = bitstream.take(n).collect();
-    assert!(bitstr.len() == n);
-    u64::from_str_radix(&bitstr, 2).unwrap()
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0, end: bytes.len() * 8 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.end
+    }
+
+    fn read(&mut self, n: usize) -> Option<u64> {
+        if self.pos + n > self.end {
+            return None;
+        }
+        let mut val = 0u64;
+        for i in self.pos..(self.pos + n) {
+            val = val << 1 | ((self.bytes[i / 8] >> (7 - i % 8)) & 1) as u64;
+        }
+        self.pos += n;
+        Some(val)
+    }
+
+    /// A reader over the next `n` bits, which this one then skips.
+    fn take(&mut self, n: usize) -> Option<BitReader<'a>> {
+        if self.pos + n > self.end {
+            return None;
+        }
+        let sub = BitReader { bytes: self.bytes, pos: self.pos, end: self.pos + n };
+        self.pos += n;
+        Some(sub)
+    }
 }
 
-fn parse_packet(bitstream: &mut dyn Iterator<Item=char>) -> Packet {
-    let version = get_bits_to_int(3, bitstream) as u8;
-    let type_id = get_bits_to_int(3, bitstream) as u8;
+/// How many operators deep a packet may be. Parsing, evaluation and the
+/// other walks over a packet tree all recurse once per level.
+const MAX_DEPTH: usize = 256;
+
+fn parse_packet(bits: &mut BitReader, depth: usize) -> Result<Packet, PacketError> {
+    let at = bits.pos;
+    if depth > MAX_DEPTH {
+        return Err(PacketError::TooDeep { at });
+    }
+    let truncated = PacketError::Truncated { at };
+    let version = bits.read(3).ok_or(truncated)? as u8;
+    let type_id = bits.read(3).ok_or(truncated)? as u8;
     match type_id {
         4 => {
             let mut val: u64 = 0;
-            while bitstream.next().unwrap() == '1' {
-                val = (val << 4) | get_bits_to_int(4, bitstream);
+            loop {
+                let group = bits.read(5).ok_or(truncated)?;
+                if val >> 60 != 0 {
+                    return Err(PacketError::LiteralOverflow { at });
+                }
+                val = (val << 4) | (group & 0xf);
+                if group & 0x10 == 0 {
+                    break;
+                }
             }
-            val = (val << 4) | get_bits_to_int(4, bitstream);
-            Packet {
+            Ok(Packet {
                 version,
                 type_id,
                 payload: Payload::Literal(val),
-            }
+            })
         },
         _ => {
             let mut subpackets: Vec<Packet> = Vec::new();
-            if bitstream.next().unwrap() == '0' {
-                let nbits = get_bits_to_int(15, bitstream);
-                let mut substring = bitstream.take(nbits as usize).peekable();
-                while substring.peek().is_some() {
-                    subpackets.push(parse_packet(&mut substring));
+            let length_type = match bits.read(1).ok_or(truncated)? {
+                0 => LengthType::Bits,
+                _ => LengthType::Count,
+            };
+            if length_type == LengthType::Bits {
+                let nbits = bits.read(15).ok_or(truncated)?;
+                let mut sub = bits.take(nbits as usize).ok_or(truncated)?;
+                while !sub.is_empty() {
+                    subpackets.push(parse_packet(&mut sub, depth + 1)?);
                 }
             } else {
-                let npackets = get_bits_to_int(11, bitstream);
+                let npackets = bits.read(11).ok_or(truncated)?;
                 for _ in 0..npackets {
-                    subpackets.push(parse_packet(bitstream));
+                    subpackets.push(parse_packet(bits, depth + 1)?);
                 }
             }
-            Packet {
+            if type_id >= 5 && subpackets.len() != 2 {
+                return Err(PacketError::BadComparison { at, count: subpackets.len() });
+            }
+            if (type_id == 2 || type_id == 3) && subpackets.is_empty() {
+                return Err(PacketError::EmptyMinMax { at });
+            }
+            Ok(Packet {
                 version,
                 type_id,
-                payload: Payload::Operator(subpackets),
+                payload: Payload::Operator(length_type, subpackets),
+            })
+        },
+    }
+}
+
+fn parse_hex(hex: &str) -> Result<Packet, PacketError> {
+    let bytes = decode_hex(hex)?;
+    parse_packet(&mut BitReader::new(&bytes), 0)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Padding {
+    /// Just enough zero bits to finish the last hex digit.
+    Nibble,
+    /// Zero bits up to a whole byte, as the puzzle transmissions are.
+    Byte,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Encoding {
+    /// Length type for every operator, or `None` to keep each packet's own.
+    length_type: Option<LengthType>,
+    padding: Padding,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding { length_type: None, padding: Padding::Byte }
+    }
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self { bytes: Vec::new(), len: 0 }
+    }
+
+    /// Appends the low `n` bits of `val`, most significant first.
+    fn push(&mut self, n: usize, val: u64) {
+        for i in (0..n).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (val >> i) & 1 == 1 {
+                self.bytes[self.len / 8] |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+
+    fn append(&mut self, other: &BitWriter) {
+        for i in 0..other.len {
+            self.push(1, ((other.bytes[i / 8] >> (7 - i % 8)) & 1) as u64);
+        }
+    }
+
+    fn to_hex(&self, padding: Padding) -> String {
+        let nibbles = match padding {
+            Padding::Nibble => self.len.div_ceil(4),
+            Padding::Byte => self.len.div_ceil(8) * 2,
+        };
+        (0..nibbles)
+            .map(|i| {
+                let byte = self.bytes.get(i / 2).copied().unwrap_or(0);
+                let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0xf };
+                char::from_digit(nibble as u32, 16).unwrap().to_ascii_uppercase()
+            })
+            .collect()
+    }
+}
+
+/// Writes `pkt` as bits, or `None` if some field doesn't fit its width or
+/// the type ID doesn't agree with the payload.
+fn write_packet(pkt: &Packet, length_type: Option<LengthType>, out: &mut BitWriter) -> Option<()> {
+    if pkt.version > 7 || pkt.type_id > 7 {
+        return None;
+    }
+    out.push(3, pkt.version as u64);
+    out.push(3, pkt.type_id as u64);
+    match &pkt.payload {
+        Payload::Literal(val) => {
+            if pkt.type_id != 4 {
+                return None;
+            }
+            let groups = (64 - val.leading_zeros() as usize).div_ceil(4).max(1);
+            for g in (0..groups).rev() {
+                out.push(1, (g > 0) as u64);
+                out.push(4, val >> (g * 4));
             }
         },
+        Payload::Operator(own, subpackets) => {
+            if pkt.type_id == 4 {
+                return None;
+            }
+            let mut sub = BitWriter::new();
+            for p in subpackets {
+                write_packet(p, length_type, &mut sub)?;
+            }
+            match length_type.unwrap_or(*own) {
+                LengthType::Bits if sub.len < 1 << 15 => {
+                    out.push(1, 0);
+                    out.push(15, sub.len as u64);
+                },
+                LengthType::Count if subpackets.len() < 1 << 11 => {
+                    out.push(1, 1);
+                    out.push(11, subpackets.len() as u64);
+                },
+                _ => return None,
+            }
+            out.append(&sub);
+        },
     }
+    Some(())
+}
+
+/// The hex transmission for `pkt`. Literals always use as few groups as
+/// possible, so a transmission only round-trips if it did too.
+fn encode(pkt: &Packet, encoding: &Encoding) -> Option<String> {
+    let mut out = BitWriter::new();
+    write_packet(pkt, encoding.length_type, &mut out)?;
+    Some(out.to_hex(encoding.padding))
 }
 
 fn sum_versions(pkt: &Packet) -> u64 {
     let mut sum = pkt.version as u64;
-    if let Payload::Operator(subpackets) = &pkt.payload {
+    if let Payload::Operator(_, subpackets) = &pkt.payload {
         for p in subpackets {
             sum += sum_versions(p);
         }
@@ -99,40 +307,333 @@ fn sum_versions(pkt: &Packet) -> u64 {
     sum
 }
 
-fn value_of(pkt: &Packet) -> u64 {
-    match &pkt.payload {
-        Payload::Literal(val) => *val,
-        Payload::Operator(subpackets) => {
-            match pkt.type_id {
-                0 => subpackets.iter().map(value_of).sum(),
-                1 => subpackets.iter().map(value_of).product(),
-                2 => subpackets.iter().map(value_of).min().unwrap(),
-                3 => subpackets.iter().map(value_of).max().unwrap(),
-                5 => if value_of(&subpackets[0]) > value_of(&subpackets[1]) { 1 } else { 0 },
-                6 => if value_of(&subpackets[0]) < value_of(&subpackets[1]) { 1 } else { 0 },
-                7 => if value_of(&subpackets[0]) == value_of(&subpackets[1]) { 1 } else { 0 },
-                _ => panic!(),
+/// Why a packet tree built some other way than parsing has no value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ValueError {
+    /// An operator that can't take this many subpackets, or type ID 4.
+    BadOperands { type_id: u8, count: usize },
+    /// A sum or product too large for 64 bits.
+    Overflow,
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueError::BadOperands { type_id, count } =>
+                write!(f, "operator type {} can't take {} subpackets", type_id, count),
+            ValueError::Overflow => write!(f, "value doesn't fit in 64 bits"),
+        }
+    }
+}
+
+fn value_of(pkt: &Packet) -> Result<u64, ValueError> {
+    let subpackets = match &pkt.payload {
+        Payload::Literal(val) => return Ok(*val),
+        Payload::Operator(_, subpackets) => subpackets,
+    };
+    let bad = ValueError::BadOperands { type_id: pkt.type_id, count: subpackets.len() };
+    let values = subpackets.iter().map(value_of).collect::<Result<Vec<u64>, ValueError>>()?;
+    match (pkt.type_id, values.as_slice()) {
+        (0, _) => values.iter().try_fold(0u64, |acc, v| acc.checked_add(*v)).ok_or(ValueError::Overflow),
+        (1, _) => values.iter().try_fold(1u64, |acc, v| acc.checked_mul(*v)).ok_or(ValueError::Overflow),
+        (2, _) => values.iter().min().copied().ok_or(bad),
+        (3, _) => values.iter().max().copied().ok_or(bad),
+        (5, [a, b]) => Ok(u64::from(a > b)),
+        (6, [a, b]) => Ok(u64::from(a < b)),
+        (7, [a, b]) => Ok(u64::from(a == b)),
+        _ => Err(bad),
+    }
+}
+
+/// Operator type IDs by the name used for them in expressions.
+const OPERATORS: [(&str, u8); 7] = [
+    ("sum", 0), ("product", 1), ("min", 2), ("max", 3), ("gt", 5), ("lt", 6), ("eq", 7),
+];
+
+#[derive(Debug, Eq, PartialEq)]
+enum ExprError {
+    /// 1-based column of something that can't go there, or `None` at the end.
+    Unexpected { col: usize, found: Option<char> },
+    /// A function that doesn't exist, or a comparison without two arguments.
+    BadCall { col: usize, name: String },
+    /// Parentheses or calls nested more than `MAX_DEPTH` deep start at this
+    /// column, or the literal there would end up in too deep a packet.
+    TooDeep { col: usize },
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExprError::Unexpected { col, found: Some(c) } => write!(f, "unexpected {:?} at column {}", c, col),
+            ExprError::Unexpected { col, found: None } => write!(f, "unexpected end at column {}", col),
+            ExprError::BadCall { col, name } => write!(f, "bad call to {} at column {}", name, col),
+            ExprError::TooDeep { col } => write!(f, "nested too deeply at column {}", col),
+        }
+    }
+}
+
+/// How many packets deep the deepest literal under a packet is, and that
+/// literal's column.
+type Height = (usize, usize);
+
+/// Recursive-descent parser turning an expression into packets:
+///
+/// ```text
+/// compare := sum (('<' | '>' | '==') sum)?
+/// sum     := product ('+' product)*
+/// product := atom ('*' atom)*
+/// atom    := (number | name '(' compare (',' compare)* ')' | '(' compare ')') ('@' version)?
+/// ```
+///
+/// Every packet has version 0 unless annotated. Each rule also returns the
+/// `Height` of its packet, since whether an operand gets wrapped in another
+/// operator isn't known until after it has been parsed.
+struct Compiler {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Compiler {
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
+    }
+
+    fn unexpected(&mut self) -> ExprError {
+        let found = self.peek();
+        ExprError::Unexpected { col: self.pos + 1, found }
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        self.peek();
+        let end = self.pos + s.len();
+        if self.chars.len() >= end && self.chars[self.pos..end].iter().copied().eq(s.chars()) {
+            self.pos = end;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), ExprError> {
+        if self.eat(s) { Ok(()) } else { Err(self.unexpected()) }
+    }
+
+    fn word(&mut self, f: fn(char) -> bool) -> String {
+        self.peek();
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(|c| f(*c)) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn number(&mut self) -> Result<u64, ExprError> {
+        let err = self.unexpected();
+        self.word(|c| c.is_ascii_digit()).parse().map_err(|_| err)
+    }
+
+    fn operator(type_id: u8, operands: Vec<(Packet, Height)>) -> (Packet, Height) {
+        let (height, col) = operands.iter().map(|(_, h)| *h).max_by_key(|(h, _)| *h).unwrap();
+        let subpackets = operands.into_iter().map(|(p, _)| p).collect();
+        let pkt = Packet { version: 0, type_id, payload: Payload::Operator(LengthType::Bits, subpackets) };
+        (pkt, (height + 1, col))
+    }
+
+    /// `depth` counts the parentheses and calls around the expression, which
+    /// bounds the recursion even where parentheses add no packets.
+    fn compare(&mut self, depth: usize) -> Result<(Packet, Height), ExprError> {
+        let lhs = self.sum(depth)?;
+        for (op, type_id) in [(">", 5), ("<", 6), ("==", 7)] {
+            if self.eat(op) {
+                let rhs = self.sum(depth)?;
+                return Ok(Self::operator(type_id, vec![lhs, rhs]));
             }
         }
+        Ok(lhs)
+    }
+
+    fn sum(&mut self, depth: usize) -> Result<(Packet, Height), ExprError> {
+        let mut terms = vec![self.product(depth)?];
+        while self.eat("+") {
+            terms.push(self.product(depth)?);
+        }
+        Ok(if terms.len() == 1 { terms.pop().unwrap() } else { Self::operator(0, terms) })
+    }
+
+    fn product(&mut self, depth: usize) -> Result<(Packet, Height), ExprError> {
+        let mut factors = vec![self.atom(depth)?];
+        while self.eat("*") {
+            factors.push(self.atom(depth)?);
+        }
+        Ok(if factors.len() == 1 { factors.pop().unwrap() } else { Self::operator(1, factors) })
+    }
+
+    fn atom(&mut self, depth: usize) -> Result<(Packet, Height), ExprError> {
+        let next = self.peek();
+        let col = self.pos + 1;
+        if depth == MAX_DEPTH && next.is_some_and(|c| c == '(' || c.is_ascii_alphabetic()) {
+            return Err(ExprError::TooDeep { col });
+        }
+        let (mut pkt, height) = match next {
+            Some('(') => {
+                self.pos += 1;
+                let inner = self.compare(depth + 1)?;
+                self.expect(")")?;
+                inner
+            },
+            Some(c) if c.is_ascii_digit() => {
+                let pkt = Packet { version: 0, type_id: 4, payload: Payload::Literal(self.number()?) };
+                (pkt, (0, col))
+            },
+            Some(c) if c.is_ascii_alphabetic() => {
+                let name = self.word(|c| c.is_ascii_alphanumeric());
+                let type_id = OPERATORS
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, t)| *t)
+                    .ok_or_else(|| ExprError::BadCall { col, name: name.clone() })?;
+                self.expect("(")?;
+                let mut args = vec![self.compare(depth + 1)?];
+                while self.eat(",") {
+                    args.push(self.compare(depth + 1)?);
+                }
+                self.expect(")")?;
+                if type_id >= 5 && args.len() != 2 {
+                    return Err(ExprError::BadCall { col, name });
+                }
+                Self::operator(type_id, args)
+            },
+            _ => return Err(self.unexpected()),
+        };
+        if self.eat("@") {
+            let err = self.unexpected();
+            pkt.version = match self.number()? {
+                v @ 0..=7 => v as u8,
+                _ => return Err(err),
+            };
+        }
+        Ok((pkt, height))
     }
 }
 
-fn part1(input: &[String]) -> u64 {
-    let mut bitstream = bitstream(&input[0]);
-    let pkt = parse_packet(&mut bitstream);
-    sum_versions(&pkt)
+/// Compiles an expression such as `max(3, 7*2) + (5 < 9)` into a packet
+/// whose value is the expression's value.
+fn compile(expr: &str) -> Result<Packet, ExprError> {
+    let mut c = Compiler { chars: expr.chars().collect(), pos: 0 };
+    let (pkt, (height, col)) = c.compare(0)?;
+    match c.peek() {
+        Some(_) => Err(c.unexpected()),
+        None if height > MAX_DEPTH => Err(ExprError::TooDeep { col }),
+        None => Ok(pkt),
+    }
 }
 
-fn part2(input: &[String]) -> u64 {
-    let mut bitstream = bitstream(&input[0]);
-    let pkt = parse_packet(&mut bitstream);
-    value_of(&pkt)
+/// Prints a packet tree as an expression `compile` accepts, each packet
+/// annotated with its version: `max(2@2, 4@4, 1@1)@7`, `(6@1 < 9@2)@0`.
+fn disassemble(pkt: &Packet) -> String {
+    match &pkt.payload {
+        Payload::Literal(val) => format!("{}@{}", val, pkt.version),
+        Payload::Operator(_, subpackets) => {
+            let infix = match (pkt.type_id, subpackets.len()) {
+                (0, n) if n >= 2 => Some(" + "),
+                (1, n) if n >= 2 => Some(" * "),
+                (5, 2) => Some(" > "),
+                (6, 2) => Some(" < "),
+                (7, 2) => Some(" == "),
+                _ => None,
+            };
+            let args = subpackets.iter().map(disassemble);
+            match infix {
+                Some(op) => format!("({})@{}", args.collect::<Vec<_>>().join(op), pkt.version),
+                None => {
+                    let name = OPERATORS
+                        .iter()
+                        .find(|(_, t)| *t == pkt.type_id)
+                        .map_or("?", |(n, _)| n);
+                    format!("{}({})@{}", name, args.collect::<Vec<_>>().join(", "), pkt.version)
+                },
+            }
+        },
+    }
+}
+
+fn part1(pkt: &Packet) -> u64 {
+    sum_versions(pkt)
+}
+
+fn part2(pkt: &Packet) -> Result<u64, ValueError> {
+    value_of(pkt)
 }
 
 fn main() {
+    // DAY16_COMPILE=<expr> prints the transmission for an expression instead
+    if let Ok(expr) = env::var("DAY16_COMPILE") {
+        let pkt = match compile(&expr) {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            },
+        };
+        match encode(&pkt, &Encoding::default()) {
+            Some(hex) => println!("{}", hex),
+            None => {
+                eprintln!("expression too large to encode");
+                std::process::exit(1);
+            },
+        }
+        return;
+    }
     let input: Vec<String> = read_input();
-    println!("Part 1: {}", part1(&input));
-    println!("Part 2: {}", part2(&input));
+    let pkt = match parse_hex(&input[0]) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+    println!("Part 1: {}", part1(&pkt));
+    match part2(&pkt) {
+        Ok(v) => println!("Part 2: {}", v),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    }
+
+    // DAY16_DISASM=1 also prints the packets as an expression
+    if env::var_os("DAY16_DISASM").is_some() {
+        println!("{}", disassemble(&pkt));
+    }
+
+    // DAY16_ENCODE=same|bits|count re-encodes the transmission, with every
+    // operator switched to that length type; DAY16_PADDING=nibble drops the
+    // padding to a whole byte
+    if let Ok(length) = env::var("DAY16_ENCODE") {
+        let length_type = match length.as_str() {
+            "same" => None,
+            "bits" => Some(LengthType::Bits),
+            "count" => Some(LengthType::Count),
+            _ => {
+                eprintln!("DAY16_ENCODE must be same, bits or count");
+                std::process::exit(1);
+            },
+        };
+        let padding = match env::var("DAY16_PADDING").as_deref() {
+            Ok("nibble") => Padding::Nibble,
+            _ => Padding::Byte,
+        };
+        match encode(&pkt, &Encoding { length_type, padding }) {
+            Some(hex) => println!("{}", hex),
+            None => {
+                eprintln!("transmission can't be encoded with {} lengths", length);
+                std::process::exit(1);
+            },
+        }
+    }
 }
 
 #[cfg(test)]
@@ -141,19 +642,17 @@ mod tests {
 
     #[test]
     fn day16_test() {
-        let bstrm:String = bitstream("ABC0123").collect();
-        assert_eq!(bstrm, "1010101111000000000100100011");
+        assert_eq!(decode_hex("ABC0123"), Ok(vec![0xAB, 0xC0, 0x12, 0x30]));
 
-        let mut bstrm = bitstream("D2FE28");
-        let pkt:Packet = parse_packet(&mut bstrm);
+        let pkt = parse_hex("D2FE28").unwrap();
         assert_eq!(pkt, Packet{version: 6, type_id: 4, payload: Payload::Literal(2021)});
 
-        let mut bstrm = bitstream("38006F45291200");
-        let pkt:Packet = parse_packet(&mut bstrm);
+        let pkt = parse_hex("38006F45291200").unwrap();
         assert_eq!(pkt, Packet {
             version: 1,
             type_id: 6,
             payload: Payload::Operator(
+                LengthType::Bits,
                 vec![
                     Packet { version: 6, type_id: 4, payload: Payload::Literal(10) },
                     Packet { version: 2, type_id: 4, payload: Payload::Literal(20) },
@@ -161,12 +660,12 @@ mod tests {
             ),
         });
 
-        let mut bstrm = bitstream("EE00D40C823060");
-        let pkt:Packet = parse_packet(&mut bstrm);
+        let pkt = parse_hex("EE00D40C823060").unwrap();
         assert_eq!(pkt, Packet {
             version: 7,
             type_id: 3,
             payload: Payload::Operator(
+                LengthType::Count,
                 vec![
                     Packet { version: 2, type_id: 4, payload: Payload::Literal(1) },
                     Packet { version: 4, type_id: 4, payload: Payload::Literal(2) },
@@ -175,52 +674,186 @@ mod tests {
             ),
         });
 
-        let mut bstrm = bitstream("8A004A801A8002F478");
-        let pkt:Packet = parse_packet(&mut bstrm);
+        let pkt = parse_hex("8A004A801A8002F478").unwrap();
         assert_eq!(sum_versions(&pkt), 16);
 
-        let mut bstrm = bitstream("620080001611562C8802118E34");
-        let pkt:Packet = parse_packet(&mut bstrm);
+        let pkt = parse_hex("620080001611562C8802118E34").unwrap();
         assert_eq!(sum_versions(&pkt), 12);
 
-        let mut bstrm = bitstream("C0015000016115A2E0802F182340");
-        let pkt:Packet = parse_packet(&mut bstrm);
+        let pkt = parse_hex("C0015000016115A2E0802F182340").unwrap();
         assert_eq!(sum_versions(&pkt), 23);
 
-        let mut bstrm = bitstream("A0016C880162017C3686B18A3D4780");
-        let pkt:Packet = parse_packet(&mut bstrm);
+        let pkt = parse_hex("A0016C880162017C3686B18A3D4780").unwrap();
         assert_eq!(sum_versions(&pkt), 31);
 
-        let mut bstrm = bitstream("C200B40A82");
-        let pkt:Packet = parse_packet(&mut bstrm);
-        assert_eq!(value_of(&pkt), 3);
+        let pkt = parse_hex("C200B40A82").unwrap();
+        assert_eq!(value_of(&pkt), Ok(3));
+
+        let pkt = parse_hex("04005AC33890").unwrap();
+        assert_eq!(value_of(&pkt), Ok(54));
+
+        let pkt = parse_hex("880086C3E88112").unwrap();
+        assert_eq!(value_of(&pkt), Ok(7));
 
-        let mut bstrm = bitstream("04005AC33890");
-        let pkt:Packet = parse_packet(&mut bstrm);
-        assert_eq!(value_of(&pkt), 54);
+        let pkt = parse_hex("CE00C43D881120").unwrap();
+        assert_eq!(value_of(&pkt), Ok(9));
 
-        let mut bstrm = bitstream("880086C3E88112");
-        let pkt:Packet = parse_packet(&mut bstrm);
-        assert_eq!(value_of(&pkt), 7);
+        let pkt = parse_hex("D8005AC2A8F0").unwrap();
+        assert_eq!(value_of(&pkt), Ok(1));
 
-        let mut bstrm = bitstream("CE00C43D881120");
-        let pkt:Packet = parse_packet(&mut bstrm);
-        assert_eq!(value_of(&pkt), 9);
+        let pkt = parse_hex("F600BC2D8F").unwrap();
+        assert_eq!(value_of(&pkt), Ok(0));
 
-        let mut bstrm = bitstream("D8005AC2A8F0");
-        let pkt:Packet = parse_packet(&mut bstrm);
-        assert_eq!(value_of(&pkt), 1);
+        let pkt = parse_hex("9C005AC2F8F0").unwrap();
+        assert_eq!(value_of(&pkt), Ok(0));
 
-        let mut bstrm = bitstream("F600BC2D8F");
-        let pkt:Packet = parse_packet(&mut bstrm);
-        assert_eq!(value_of(&pkt), 0);
+        let pkt = parse_hex("9C0141080250320F1802104A08").unwrap();
+        assert_eq!(value_of(&pkt), Ok(1));
+    }
+
+    #[test]
+    fn day16_encode_test() {
+        let transmissions = [
+            "D2FE28", "38006F45291200", "EE00D40C823060", "8A004A801A8002F478",
+            "620080001611562C8802118E34", "C0015000016115A2E0802F182340",
+            "A0016C880162017C3686B18A3D4780", "C200B40A82", "04005AC33890",
+            "880086C3E88112", "CE00C43D881120", "D8005AC2A8F0", "F600BC2D8F",
+            "9C005AC2F8F0", "9C0141080250320F1802104A08",
+        ];
+        for hex in transmissions {
+            let pkt = parse_hex(hex).unwrap();
+            assert_eq!(encode(&pkt, &Encoding::default()).unwrap(), hex);
+            for length_type in [LengthType::Bits, LengthType::Count] {
+                let enc = Encoding { length_type: Some(length_type), padding: Padding::Nibble };
+                let other = parse_hex(&encode(&pkt, &enc).unwrap()).unwrap();
+                assert_eq!(value_of(&other), value_of(&pkt));
+                assert_eq!(sum_versions(&other), sum_versions(&pkt));
+            }
+        }
+
+        let lit = |v| Packet { version: 1, type_id: 4, payload: Payload::Literal(v) };
+        assert_eq!(encode(&lit(0), &Encoding { length_type: None, padding: Padding::Nibble }).unwrap(), "300");
+        assert_eq!(encode(&lit(u64::MAX), &Encoding::default()).map(|h| h.len()), Some(22));
+        assert_eq!(parse_hex(&encode(&lit(u64::MAX), &Encoding::default()).unwrap()), Ok(lit(u64::MAX)));
+        let pkt = Packet { version: 5, type_id: 1, payload: Payload::Operator(LengthType::Count, vec![lit(6), lit(7)]) };
+        let hex = encode(&pkt, &Encoding::default()).unwrap();
+        assert_eq!(parse_hex(&hex).unwrap(), pkt);
+        assert_eq!(value_of(&pkt), Ok(42));
+        let bad = Packet { version: 8, ..lit(1) };
+        assert_eq!(encode(&bad, &Encoding::default()), None);
+        let bad = Packet { version: 0, type_id: 4, payload: Payload::Operator(LengthType::Bits, vec![]) };
+        assert_eq!(encode(&bad, &Encoding::default()), None);
+        let many = Packet { version: 0, type_id: 0, payload: Payload::Operator(LengthType::Count, vec![lit(1); 2048]) };
+        assert_eq!(encode(&many, &Encoding::default()), None);
+        assert!(encode(&many, &Encoding { length_type: Some(LengthType::Bits), ..Encoding::default() }).is_some());
+    }
+
+    #[test]
+    fn day16_compile_test() {
+        let pkt = compile("max(3, 7*2, min(1,4)) + (5 < 9)").unwrap();
+        assert_eq!(value_of(&pkt), Ok(15));
+        let hex = encode(&pkt, &Encoding::default()).unwrap();
+        assert_eq!(value_of(&parse_hex(&hex).unwrap()), Ok(15));
+        assert_eq!(value_of(&compile("1 + 2 * 3 == 7").unwrap()), Ok(1));
+        assert_eq!(value_of(&compile("(1 + 2) * 3").unwrap()), Ok(9));
+        assert_eq!(value_of(&compile(" gt( 2 , product(3) ) ").unwrap()), Ok(0));
+        assert_eq!(sum_versions(&compile("(1@2 + 3@4)@7").unwrap()), 13);
+
+        assert_eq!(compile("1 +"), Err(ExprError::Unexpected { col: 4, found: None }));
+        assert_eq!(compile("2 3"), Err(ExprError::Unexpected { col: 3, found: Some('3') }));
+        assert_eq!(compile("(1 + 2"), Err(ExprError::Unexpected { col: 7, found: None }));
+        assert_eq!(compile("4@8"), Err(ExprError::Unexpected { col: 3, found: Some('8') }));
+        assert_eq!(compile("1 + avg(2, 3)"), Err(ExprError::BadCall { col: 5, name: "avg".into() }));
+        assert_eq!(compile("lt(1, 2, 3)"), Err(ExprError::BadCall { col: 1, name: "lt".into() }));
 
-        let mut bstrm = bitstream("9C005AC2F8F0");
-        let pkt:Packet = parse_packet(&mut bstrm);
-        assert_eq!(value_of(&pkt), 0);
+        let deep = |n, inner: &str| format!("{}{}{}", "sum(".repeat(n), inner, ")".repeat(n));
+        let pkt = compile(&deep(MAX_DEPTH, "1")).unwrap();
+        let hex = encode(&pkt, &Encoding::default()).unwrap();
+        assert_eq!(value_of(&parse_hex(&hex).unwrap()), Ok(1));
+        assert_eq!(compile(&deep(MAX_DEPTH + 1, "1")), Err(ExprError::TooDeep { col: 4 * MAX_DEPTH + 1 }));
+        assert_eq!(compile(&deep(MAX_DEPTH, "2 < 3")), Err(ExprError::TooDeep { col: 4 * MAX_DEPTH + 5 }));
+        assert_eq!(compile(&deep(MAX_DEPTH - 1, "(2) < 3")).map(|p| value_of(&p)), Ok(Ok(1)));
+        assert_eq!(compile(&"(".repeat(60_000)), Err(ExprError::TooDeep { col: MAX_DEPTH + 1 }));
 
-        let mut bstrm = bitstream("9C0141080250320F1802104A08");
-        let pkt:Packet = parse_packet(&mut bstrm);
-        assert_eq!(value_of(&pkt), 1);
+        let pkt = parse_hex("38006F45291200").unwrap();
+        assert_eq!(disassemble(&pkt), "(10@6 < 20@2)@1");
+        let pkt = parse_hex("EE00D40C823060").unwrap();
+        assert_eq!(disassemble(&pkt), "max(1@2, 2@4, 3@1)@7");
+        let pkt = parse_hex("9C0141080250320F1802104A08").unwrap();
+        assert_eq!(disassemble(&pkt), "((1@2 + 3@4)@2 == (2@0 * 2@2)@6)@4");
+        for hex in ["8A004A801A8002F478", "C0015000016115A2E0802F182340", "A0016C880162017C3686B18A3D4780"] {
+            let pkt = parse_hex(hex).unwrap();
+            let again = compile(&disassemble(&pkt)).unwrap();
+            assert_eq!(disassemble(&again), disassemble(&pkt));
+            assert_eq!(sum_versions(&again), sum_versions(&pkt));
+        }
+    }
+
+    #[test]
+    fn day16_reader_test() {
+        assert_eq!(parse_hex("d2FE28"), Err(PacketError::BadDigit { col: 1, found: 'd' }));
+        assert_eq!(parse_hex("D2FG28"), Err(PacketError::BadDigit { col: 4, found: 'G' }));
+        assert_eq!(parse_hex("D2FE"), Err(PacketError::Truncated { at: 0 }));
+        assert_eq!(parse_hex(""), Err(PacketError::Truncated { at: 0 }));
+        // the second subpacket of 38006F45291200 cut short
+        assert_eq!(parse_hex("38006F4529"), Err(PacketError::Truncated { at: 0 }));
+        assert_eq!(parse_hex("EE00D40C82"), Err(PacketError::Truncated { at: 40 }));
+        let lit = |v| Packet { version: 1, type_id: 4, payload: Payload::Literal(v) };
+        let three = Packet { version: 0, type_id: 7, payload: Payload::Operator(LengthType::Count, vec![lit(1); 3]) };
+        let hex = encode(&three, &Encoding::default()).unwrap();
+        assert_eq!(parse_hex(&hex), Err(PacketError::BadComparison { at: 0, count: 3 }));
+        assert_eq!(value_of(&three), Err(ValueError::BadOperands { type_id: 7, count: 3 }));
+        // 000 010 1 00000000000: a minimum of nothing
+        assert_eq!(parse_hex("0A0000"), Err(PacketError::EmptyMinMax { at: 0 }));
+        let empty = Packet { version: 0, type_id: 3, payload: Payload::Operator(LengthType::Bits, vec![]) };
+        assert_eq!(value_of(&empty), Err(ValueError::BadOperands { type_id: 3, count: 0 }));
+        let bad = Packet { version: 0, type_id: 4, payload: Payload::Operator(LengthType::Bits, vec![lit(1)]) };
+        assert_eq!(value_of(&bad), Err(ValueError::BadOperands { type_id: 4, count: 1 }));
+
+        // a literal of 17 groups only fits if the first is zero
+        let literal = |first: u64| {
+            let mut w = BitWriter::new();
+            w.push(6, 0b001100);
+            w.push(5, 0x10 | first);
+            for _ in 0..15 {
+                w.push(5, 0x1f);
+            }
+            w.push(5, 0x0f);
+            w.to_hex(Padding::Byte)
+        };
+        assert_eq!(parse_hex(&literal(0)), Ok(lit(u64::MAX)));
+        assert_eq!(parse_hex(&literal(1)), Err(PacketError::LiteralOverflow { at: 0 }));
+        let max = u64::MAX.to_string();
+        assert_eq!(value_of(&compile(&format!("{} + 1", max)).unwrap()), Err(ValueError::Overflow));
+        assert_eq!(value_of(&compile("4294967296 * 4294967296").unwrap()), Err(ValueError::Overflow));
+        assert_eq!(value_of(&compile(&format!("{} * 1 + 0", max)).unwrap()), Ok(u64::MAX));
+
+        let inner = Packet { version: 2, type_id: 0, payload: Payload::Operator(LengthType::Bits, vec![lit(7); 1000]) };
+        let outer = Packet { version: 3, type_id: 0, payload: Payload::Operator(LengthType::Count, vec![inner; 300]) };
+        let hex = encode(&outer, &Encoding::default()).unwrap();
+        assert!(hex.len() > 800_000);
+        let pkt = parse_hex(&hex).unwrap();
+        assert_eq!(value_of(&pkt), Ok(7 * 300_000));
+        assert_eq!(sum_versions(&pkt), 3 + 300 * 2 + 300_000);
+
+        // a chain of sum packets with one subpacket each, 18 bits apiece
+        let nested = |depth: usize| {
+            let mut w = BitWriter::new();
+            for _ in 0..depth {
+                w.push(7, 0b0010001);
+                w.push(11, 1);
+            }
+            // version 1 literal 7
+            w.push(6, 0b001100);
+            w.push(5, 7);
+            w.to_hex(Padding::Byte)
+        };
+        let pkt = parse_hex(&nested(MAX_DEPTH)).unwrap();
+        assert_eq!(value_of(&pkt), Ok(7));
+        assert_eq!(sum_versions(&pkt), MAX_DEPTH as u64 + 1);
+        assert_eq!(parse_hex(&nested(MAX_DEPTH + 1)), Err(PacketError::TooDeep { at: 18 * (MAX_DEPTH + 1) }));
+        let hex = nested(200_000);
+        assert!(hex.len() > 800_000);
+        assert_eq!(parse_hex(&hex), Err(PacketError::TooDeep { at: 18 * (MAX_DEPTH + 1) }));
     }
 }
@@ -1,4 +1,6 @@
 #[macro_use] extern crate lazy_static;
+use std::env;
+use std::fs;
 use std::ops::Range;
 use std::str::FromStr;
 use std::vec::Vec;
@@ -32,96 +34,251 @@ impl FromStr for TargetArea {
     }
 }
 
-enum ShotResult {
-    Undershot,
-    Overshot,
-    Missed,
-    Hit(i64),
-}
-
-fn sim_shot(x_vel: i64, y_vel: i64, target: &TargetArea) -> ShotResult {
-    let mut x_pos = 0;
-    let mut y_pos = 0;
-    let mut x_vel = x_vel;
-    let mut y_vel = y_vel;
-    let mut max_y = 0;
-    loop {
-        if target.x.contains(&x_pos) && target.y.contains(&y_pos) {
-            return ShotResult::Hit(max_y);
-        }
-        if y_pos < target.y.start && x_pos < target.x.end {
-            return ShotResult::Undershot;
-        }
-        if x_pos >= target.x.end && y_pos <= target.y.start {
-            return ShotResult::Overshot;
+/// Steps during which one coordinate of the probe is inside the target's
+/// range, as inclusive `(first, last)` pairs; `last` is `None` if the
+/// coordinate stays inside for good.
+type Windows = Vec<(i64, Option<i64>)>;
+
+fn windows_while<F>(range: &Range<i64>, mut pos: i64, mut vel: i64, mut drag: F) -> Windows
+        where F: FnMut(i64, i64) -> Option<i64> {
+    let mut out = Windows::new();
+    let mut open = None;
+    for t in 0.. {
+        match (range.contains(&pos), open) {
+            (true, None) => { open = Some(t); },
+            (false, Some(s)) => { out.push((s, Some(t - 1))); open = None; },
+            _ => {},
         }
-        if x_pos >= target.x.end && y_pos < target.y.start {
-            return ShotResult::Missed;
+        match drag(pos, vel) {
+            Some(v) => { pos += vel; vel = v; },
+            None => {
+                out.extend(open.map(|s| (s, None)));
+                break;
+            },
         }
-        x_pos += x_vel;
-        y_pos += y_vel;
-        x_vel += match x_vel {
-            ..=-1 => 1,
-            0 => 0,
-            1.. => -1,
-        };
-        y_vel -= 1;
-        max_y = max_y.max(y_pos);
     }
+    out
+}
+
+/// X slows towards zero and then stays put.
+fn x_windows(vx: i64, range: &Range<i64>) -> Windows {
+    windows_while(range, 0, vx, |_, v| (v != 0).then(|| v - v.signum()))
+}
+
+/// Y keeps falling; once it is falling and below the range it never returns.
+fn y_windows(vy: i64, range: &Range<i64>) -> Windows {
+    windows_while(range, 0, vy, |p, v| (v >= 0 || p >= range.start).then_some(v - 1))
+}
+
+fn overlaps(a: &Windows, b: &Windows) -> bool {
+    let before = |first: i64, last: Option<i64>| last.is_none_or(|l| first <= l);
+    a.iter().any(|(a0, a1)| b.iter().any(|(b0, b1)| before(*a0, *b1) && before(*b0, *a1)))
+}
+
+fn apex(vy: i64) -> i64 {
+    if vy > 0 { vy * (vy + 1) / 2 } else { 0 }
 }
 
-fn find_target(target: &TargetArea) -> (i64, i64, Option<i64>) {
-    let mut x = 0;
-    let mut y = 0;
-    let mut max_y = None;
-    loop {
-        match sim_shot(x, y, target) {
-            ShotResult::Undershot => { x += 1; y += 1; },
-            ShotResult::Hit(hgt) => { max_y = Some(hgt); break; },
-            _ => { break; },
+/// Every launch velocity that puts the probe inside the target at some
+/// step, found by intersecting per-axis step windows. `None` if infinitely
+/// many do: when the target holds the launcher, or when it spans y=0 and
+/// some x velocity comes to rest inside it (any high lob then lands there).
+fn solve(target: &TargetArea) -> Option<Vec<(i64, i64)>> {
+    if target.x.contains(&0) && target.y.contains(&0) {
+        return None;
+    }
+    // x(1) = vx, and x never moves back past it
+    let xs: Vec<(i64, Windows)> = (target.x.start.min(0)..=(target.x.end - 1).max(0))
+        .map(|vx| (vx, x_windows(vx, &target.x)))
+        .filter(|(_, w)| !w.is_empty())
+        .collect();
+    let ends: Vec<Option<i64>> = xs.iter().flat_map(|(_, w)| w.iter().map(|(_, l)| *l)).collect();
+    // y(1) = vy, and a lob with vy >= 0 comes back through y=0 at speed vy + 1
+    let vy_max = if target.y.end <= 0 {
+        -target.y.start - 1
+    } else if target.y.start > 0 {
+        target.y.end - 1
+    } else {
+        if ends.contains(&None) {
+            return None;
         }
+        // by the last step x can be in range, any higher lob is still above the target
+        target.y.end + ends.iter().flatten().max().unwrap_or(&0)
+    };
+    let ys: Vec<(i64, Windows)> = (target.y.start.min(0)..=vy_max)
+        .map(|vy| (vy, y_windows(vy, &target.y)))
+        .filter(|(_, w)| !w.is_empty())
+        .collect();
+    Some(xs
+        .iter()
+        .flat_map(|(vx, xw)| ys
+            .iter()
+            .filter(|(_, yw)| overlaps(xw, yw))
+            .map(|(vy, _)| (*vx, *vy)))
+        .collect())
+}
+
+/// Positions of the probe from launch until it first lands in the target,
+/// or until it is falling below the target and can never come back.
+fn trajectory(vx: i64, vy: i64, target: &TargetArea) -> Vec<(i64, i64)> {
+    let (mut x, mut y, mut dx, mut dy) = (0, 0, vx, vy);
+    let mut out = vec![(x, y)];
+    while !(target.x.contains(&x) && target.y.contains(&y)) && (dy >= 0 || y >= target.y.start) {
+        x += dx;
+        y += dy;
+        dx -= dx.signum();
+        dy -= 1;
+        out.push((x, y));
     }
-    (x, y, max_y)
-}
-
-fn find_all_hits(target: &TargetArea) -> (i64, usize) {
-    let (cx, cy, max_y) = find_target(target);
-    let mut needhit = max_y.is_none();
-    let mut max_y = max_y.unwrap_or(0);
-    let mut n_hits = if needhit { 0 } else { 1 };
-    let mut direct_hit = false;
-    for r in 1.. {
-        let mut nohits = true;
-        ((cx-r)..=(cx+r)).map(|x| (x, cy-r))
-            .chain(
-                ((cx-r)..=(cx+r)).map(|x| (x, cy+r))
-            ).chain(
-                ((cy-r+1)..=(cy+r-1)).map(|y| (cx-r, y))
-            ).chain(
-                ((cy-r+1)..=(cy+r-1)).map(|y| (cx+r, y))
-            ).for_each(|(x, y)| {
-                if target.x.contains(&x) && target.y.contains(&y) {
-                    direct_hit = true;
-                }
-                if let ShotResult::Hit(hgt) = sim_shot(x, y, target) {
-                    needhit = false;
-                    nohits = false;
-                    max_y = max_y.max(hgt);
-                    n_hits += 1;
-                }
+    out
+}
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+enum Shot {
+    Hit { step: usize, apex: i64 },
+    /// Dropped below the target without getting as far as it.
+    Undershot,
+    /// Dropped below the target beyond its far side.
+    Overshot,
+    /// Was level with the target but fell through it between steps.
+    Missed,
+}
+
+fn classify(vx: i64, vy: i64, target: &TargetArea) -> Shot {
+    let path = trajectory(vx, vy, target);
+    let (x, y) = *path.last().unwrap();
+    if target.x.contains(&x) && target.y.contains(&y) {
+        Shot::Hit { step: path.len() - 1, apex: apex(vy) }
+    } else if target.x.contains(&x) {
+        Shot::Missed
+    } else if (target.x.start > 0 && x < target.x.start) || (target.x.end <= 0 && x >= target.x.end) {
+        Shot::Undershot
+    } else {
+        Shot::Overshot
+    }
+}
+
+/// The puzzle's diagram: `S` for the launcher, `#` for each position, `T`
+/// for the target and `^` for the highest point of the trajectory.
+fn render_ascii(target: &TargetArea, path: &[(i64, i64)]) -> String {
+    let top = path.iter().map(|p| p.1).max().unwrap().max(target.y.end - 1);
+    let apex = *path.iter().find(|p| p.1 == top).unwrap_or(&path[0]);
+    let xs = path.iter().map(|p| p.0).chain([target.x.start, target.x.end - 1]);
+    let (left, right) = (xs.clone().min().unwrap(), xs.max().unwrap());
+    let bottom = path.iter().map(|p| p.1).min().unwrap().min(target.y.start);
+    let mut out = String::new();
+    for y in (bottom..=top).rev() {
+        for x in left..=right {
+            out.push(if (x, y) == (0, 0) {
+                'S'
+            } else if (x, y) == apex {
+                '^'
+            } else if path.contains(&(x, y)) {
+                '#'
+            } else if target.x.contains(&x) && target.y.contains(&y) {
+                'T'
+            } else {
+                '.'
             });
-        if nohits && !needhit && direct_hit {
-            break;
         }
+        out.push('\n');
     }
-    (max_y, n_hits)
+    out
+}
+
+/// The same picture as an SVG, one unit per grid cell, with y pointing up.
+fn render_svg(target: &TargetArea, path: &[(i64, i64)]) -> String {
+    let xs = path.iter().map(|p| p.0).chain([target.x.start, target.x.end]);
+    let ys = path.iter().map(|p| -p.1).chain([-target.y.start, -target.y.end]);
+    let (left, right) = (xs.clone().min().unwrap() - 1, xs.max().unwrap() + 1);
+    let (top, bottom) = (ys.clone().min().unwrap() - 1, ys.max().unwrap() + 1);
+    let apex = path.iter().min_by_key(|p| -p.1).unwrap();
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+        left, top, right - left, bottom - top,
+    );
+    out += &format!(
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#9cf\"/>\n",
+        target.x.start as f64 - 0.5, -target.y.end as f64 + 0.5,
+        target.x.end - target.x.start, target.y.end - target.y.start,
+    );
+    let points: Vec<String> = path.iter().map(|(x, y)| format!("{},{}", x, -y)).collect();
+    out += &format!(
+        "<polyline points=\"{}\" fill=\"none\" stroke=\"black\" stroke-width=\"0.1\"/>\n",
+        points.join(" "),
+    );
+    for (x, y) in path {
+        out += &format!("<circle cx=\"{}\" cy=\"{}\" r=\"0.2\"/>\n", x, -y);
+    }
+    out += &format!("<circle cx=\"{}\" cy=\"{}\" r=\"0.4\" fill=\"red\"/>\n", apex.0, -apex.1);
+    out += "</svg>\n";
+    out
+}
+
+/// How every velocity in the given ranges fares, one row per y velocity
+/// from the highest down: `#` hit, `<` undershot, `>` overshot, `.` missed.
+fn shot_map(target: &TargetArea, vx: Range<i64>, vy: Range<i64>) -> String {
+    vy.rev()
+        .map(|y| vx
+            .clone()
+            .map(|x| match classify(x, y, target) {
+                Shot::Hit { .. } => '#',
+                Shot::Undershot => '<',
+                Shot::Overshot => '>',
+                Shot::Missed => '.',
+            })
+            .chain(std::iter::once('\n'))
+            .collect::<String>())
+        .collect()
 }
 
 fn main() {
     let input: Vec<TargetArea> = read_input();
-    let (max_y, n_hits) = find_all_hits(&input[0]);
+    let Some(hits) = solve(&input[0]) else {
+        println!("Infinitely many velocities hit the target");
+        return;
+    };
+    let max_y = hits.iter().map(|(_, vy)| apex(*vy)).max().unwrap_or(0);
     println!("Part 1: {max_y}");
-    println!("Part 2: {n_hits}");
+    println!("Part 2: {}", hits.len());
+
+    // DAY17_MAP=1 also maps how every velocity around the hits fares
+    if env::var_os("DAY17_MAP").is_some() && !hits.is_empty() {
+        let (vx, vy): (Vec<i64>, Vec<i64>) = hits.iter().copied().unzip();
+        let span = |v: Vec<i64>| (v.iter().min().unwrap() - 1)..(v.iter().max().unwrap() + 2);
+        print!("{}", shot_map(&input[0], span(vx), span(vy)));
+    }
+
+    // DAY17_SHOT=<vx>,<vy> draws one trajectory, by default the highest hit;
+    // DAY17_SVG=<file> writes it as an SVG as well
+    let shot = env::var("DAY17_SHOT").ok();
+    let svg = env::var("DAY17_SVG").ok();
+    if shot.is_none() && svg.is_none() {
+        return;
+    }
+    let velocity = match shot.as_deref().map(|s| s.split_once(',')) {
+        None => hits.iter().copied().max_by_key(|(_, vy)| *vy),
+        Some(Some((x, y))) => x.trim().parse().ok().zip(y.trim().parse().ok()),
+        Some(None) => None,
+    };
+    let Some((vx, vy)) = velocity else {
+        eprintln!("DAY17_SHOT must be <vx>,<vy>");
+        std::process::exit(1);
+    };
+    match classify(vx, vy, &input[0]) {
+        Shot::Hit { step, apex } => println!("{},{} hits at step {}, peaking at {}", vx, vy, step, apex),
+        Shot::Undershot => println!("{},{} falls short", vx, vy),
+        Shot::Overshot => println!("{},{} overshoots", vx, vy),
+        Shot::Missed => println!("{},{} falls through", vx, vy),
+    }
+    let path = trajectory(vx, vy, &input[0]);
+    print!("{}", render_ascii(&input[0], &path));
+    if let Some(svg) = svg {
+        if let Err(e) = fs::write(&svg, render_svg(&input[0], &path)) {
+            eprintln!("{}: {}", svg, e);
+            std::process::exit(1);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -132,8 +289,76 @@ mod tests {
     #[test]
     fn day17_test() {
         let input: Vec<TargetArea> = test_input("target area: x=20..30, y=-10..-5");
-        let (max_y, n_hits) = find_all_hits(&input[0]);
-        assert_eq!(max_y, 45);
-        assert_eq!(n_hits, 112);
+        let hits = solve(&input[0]).unwrap();
+        assert_eq!(hits.iter().map(|(_, vy)| apex(*vy)).max(), Some(45));
+        assert_eq!(hits.len(), 112);
+    }
+
+    #[test]
+    fn day17_quadrant_test() {
+        let brute = |target: &TargetArea| {
+            let mut hits = Vec::new();
+            for vx in -60..=60 {
+                for vy in -60..=60 {
+                    let (mut x, mut y, mut dx, mut dy) = (0, 0, vx, vy);
+                    for _ in 0..500 {
+                        if target.x.contains(&x) && target.y.contains(&y) {
+                            hits.push((vx, vy));
+                            break;
+                        }
+                        x += dx;
+                        y += dy;
+                        dx -= dx.signum();
+                        dy -= 1;
+                    }
+                }
+            }
+            hits
+        };
+        for area in [
+            "x=20..30, y=-10..-5", "x=-30..-20, y=-10..-5", "x=20..30, y=5..10",
+            "x=-30..-20, y=5..10", "x=-5..5, y=-10..-5", "x=-3..4, y=12..14",
+            "x=22..27, y=-5..5", "x=0..0, y=-8..-8",
+        ] {
+            let target: TargetArea = area.parse().unwrap();
+            assert_eq!(solve(&target), Some(brute(&target)), "{}", area);
+        }
+        for area in ["x=20..30, y=-5..5", "x=-2..2, y=-1..1", "x=-28..-21, y=0..0"] {
+            assert_eq!(solve(&area.parse().unwrap()), None, "{}", area);
+        }
+        assert_eq!(x_windows(7, &(20..31)), vec![(4, None)]);
+        assert_eq!(y_windows(2, &(-10..-4)), vec![(7, Some(7))]);
+        assert_eq!(y_windows(6, &(5..11)), vec![(1, Some(1)), (12, Some(12))]);
+    }
+
+    #[test]
+    fn day17_render_test() {
+        let target: TargetArea = "target area: x=20..30, y=-10..-5".parse().unwrap();
+        let path = trajectory(7, 2, &target);
+        assert_eq!(path, vec![(0, 0), (7, 2), (13, 3), (18, 3), (22, 2), (25, 0), (27, -3), (28, -7)]);
+        let ascii = render_ascii(&target, &path);
+        assert_eq!(ascii.lines().next(), Some(".............^....#............"));
+        assert_eq!(ascii.lines().nth(3), Some("S........................#....."));
+        assert_eq!(ascii.lines().last(), Some("....................TTTTTTTTTTT"));
+        assert_eq!(ascii.matches('#').count(), 6);
+        let svg = render_svg(&target, &path);
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<circle").count(), 9);
+        assert!(svg.contains("<circle cx=\"13\" cy=\"-3\" r=\"0.4\" fill=\"red\"/>"));
+
+        assert_eq!(classify(7, 2, &target), Shot::Hit { step: 7, apex: 3 });
+        assert_eq!(classify(6, 9, &target), Shot::Hit { step: 20, apex: 45 });
+        assert_eq!(classify(17, -4, &target), Shot::Overshot);
+        assert_eq!(classify(3, 3, &target), Shot::Undershot);
+        assert_eq!(classify(6, 10, &target), Shot::Missed);
+        let map = shot_map(&target, 0..31, -10..10);
+        assert_eq!(map.matches('#').count(), solve(&target).unwrap().len());
+        assert_eq!(map.lines().count(), 20);
+
+        let above: TargetArea = "target area: x=-30..-20, y=5..10".parse().unwrap();
+        assert_eq!(classify(-7, 9, &above), Shot::Hit { step: 18, apex: 45 });
+        assert_eq!(classify(-5, 9, &above), Shot::Undershot);
+        assert_eq!(classify(-30, 1, &above), Shot::Overshot);
+        assert_eq!(trajectory(-5, 9, &above).last(), Some(&(-15, 0)));
     }
 }
@@ -1,267 +1,216 @@
-use std::cell::{Ref, RefCell};
+use std::env;
 use std::fmt;
-use std::rc::Rc;
 use std::str::FromStr;
 use std::vec::Vec;
 use itertools::Itertools;
 use ya_advent_lib::read::read_input;
 
-#[derive(Clone, Debug)]
-struct Node(Rc<RefCell<Element>>);
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Leaf {
+    /// Parsed numbers are limited to `u32`, so explosions adding them
+    /// together can't overflow.
+    val: u64,
+    /// Number of pairs enclosing this regular number.
+    depth: u8,
+}
 
-impl Node {
-    fn new(element: Element) -> Self {
-        Node(
-            Rc::new(RefCell::new(element))
-        )
-    }
-    fn borrow(&self) -> Ref<Element> {
-        self.0.borrow()
-    }
-    fn replace(&self, element: Element) -> Element {
-        self.0.replace(element)
-    }
-    fn duplicate(&self) -> Node {
-        Node(
-            Rc::new(RefCell::new(self.borrow().duplicate()))
-        )
-    }
+/// How deep a parsed number may nest, leaving room for `join` to put it
+/// one pair deeper.
+const MAX_DEPTH: u8 = u8::MAX - 1;
+
+/// A snailfish number flattened to its regular numbers from left to right.
+/// The tree shape is implied by the depths: a pair's left part is followed
+/// directly by its right part.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct SFNum(Vec<Leaf>);
+
+#[derive(Debug, Eq, PartialEq)]
+struct ParseError {
+    /// 1-based column of the offending character.
+    col: usize,
+    expected: &'static str,
+    /// What was there instead, or `None` at the end of the line.
+    found: Option<char>,
 }
 
-impl fmt::Display for Node {
+impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0.borrow())
+        match self.found {
+            Some(c) => write!(f, "column {}: expected {}, found {:?}", self.col, self.expected, c),
+            None => write!(f, "column {}: expected {}, found end of line", self.col, self.expected),
+        }
     }
 }
 
-#[derive(Clone, Debug)]
-enum Element {
-    Single(u8),
-    Pair(Node, Node),
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    leaves: Vec<Leaf>,
 }
 
-impl fmt::Display for Element {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Element::Single(v) => write!(f, "{v}"),
-            Element::Pair(a, b) => write!(f, "[{a},{b}]"),
+impl Parser {
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
         }
+        self.chars.get(self.pos).copied()
     }
-}
 
-impl Element {
-    fn add_singles(&self, other: &Element) -> Self {
-        match (self, other) {
-            (Element::Single(a), Element::Single(b)) => Element::Single(a + b),
-            _ => panic!(),
-        }
+    fn error(&mut self, expected: &'static str) -> ParseError {
+        let found = self.peek();
+        ParseError { col: self.pos + 1, expected, found }
     }
-    fn duplicate(&self) -> Self {
-        match self {
-            Element::Single(v) => Element::Single(*v),
-            Element::Pair(a, b) => Element::Pair(a.duplicate(), b.duplicate()),
+
+    fn expect(&mut self, c: char, expected: &'static str) -> Result<(), ParseError> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(expected))
         }
     }
-}
 
-#[derive(Clone, Debug)]
-struct SFNum (
-    Node, // this is expected to be a node containing Element::Pair
-);
-
-fn parse_element(itr: &mut dyn Iterator<Item=char>) -> Option<Element> {
-    let first = itr.next()?;
-    match first {
-        '[' => {
-            let sa = parse_element(itr)?;
-            if itr.next()? != ',' { return None }
-            let sb = parse_element(itr)?;
-            if itr.next()? != ']' { return None }
-            Some(Element::Pair(Node::new(sa), Node::new(sb)))
-        },
-        '0'..='9' => {
-            // assuming inputs are always "reduced", i.e. no values > 9
-            let val = first as u8 - b'0';
-            Some(Element::Single(val))
-        },
-        _ => None
+    fn element(&mut self, depth: u8) -> Result<(), ParseError> {
+        match self.peek() {
+            Some('[') => {
+                if depth == MAX_DEPTH {
+                    return Err(self.error("shallower nesting"));
+                }
+                self.pos += 1;
+                let depth = depth + 1;
+                self.element(depth)?;
+                self.expect(',', "','")?;
+                self.element(depth)?;
+                self.expect(']', "']'")
+            },
+            Some(c) if c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.chars.get(self.pos).is_some_and(|c| c.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+                let val: u32 = self.chars[start..self.pos]
+                    .iter()
+                    .collect::<String>()
+                    .parse()
+                    .map_err(|_| ParseError { col: start + 1, expected: "a smaller number", found: Some(c) })?;
+                self.leaves.push(Leaf { val: val.into(), depth });
+                Ok(())
+            },
+            _ => Err(self.error("'[' or a number")),
+        }
     }
 }
 
 impl FromStr for SFNum {
-    type Err = ();
+    type Err = ParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut itr = s.chars().peekable();
-        if let Some(e) = parse_element(&mut itr) {
-            Ok(SFNum(Node::new(e)))
-        }
-        else {
-            Err(())
+        let mut parser = Parser { chars: s.chars().collect(), pos: 0, leaves: Vec::new() };
+        parser.element(0)?;
+        match parser.peek() {
+            None => Ok(SFNum(parser.leaves)),
+            Some(_) => Err(parser.error("end of line")),
         }
     }
 }
 
-enum TResult {
-    NoChange,
-    Reduced,
-    AddRight(u8),
-    Explode,
+/// One step of a reduction, with the number as it stood afterwards.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Step {
+    Explode(u64, u64, SFNum),
+    Split(u64, SFNum),
 }
 
-
-fn traverse_explode(node: &Node, depth: usize, left_num: &RefCell<Option<Node>>, right_num: Option<u8>) -> TResult {
-    //println!("traversing: {node} depth={depth}");
-    let mut right_num = right_num;
-    let mut traverse_left: Option<Node> = None;
-    let mut traverse_right: Option<Node> = None;
-    let mut handle_single: Option<u8> = None;
-    match &*node.borrow() {
-        Element::Pair(left, right) => {
-            if depth >= 4 && right_num.is_none() {
-                return TResult::Explode;
-            } else {
-                traverse_left = Some(left.clone());
-                traverse_right = Some(right.clone());
-            }
-        },
-        Element::Single(val) => {
-            handle_single = Some(*val);
-        },
-    }
-    if traverse_left.is_some() {
-        let left = traverse_left.unwrap();
-        let right = traverse_right.unwrap();
-        match traverse_explode(&left, depth + 1, left_num, right_num) {
-            TResult::Reduced => { return TResult::Reduced; },
-            TResult::AddRight(val) => { right_num = Some(val) },
-            TResult::NoChange => {},
-            TResult::Explode => {
-                let oldleft = left.replace(Element::Single(0));
-                match oldleft {
-                    Element::Pair(a, b) => {
-                        let leftopt = left_num.borrow();
-                        if let Some(leftelem) = &*leftopt {
-                            let new = a.borrow().add_singles(&leftelem.borrow());
-                            leftelem.replace(new);
-                        }
-                        if let Element::Single(rn) = *b.borrow() {
-                            right_num = Some(rn);
-                        }
-                    },
-                    other => panic!("unexpected element {other:?}"),
-                }
-            },
-        };
-        match traverse_explode(&right, depth + 1, left_num, right_num) {
-            TResult::Reduced => { return TResult::Reduced; },
-            TResult::AddRight(val) => { return TResult::AddRight(val); },
-            TResult::NoChange => {},
-            TResult::Explode => {
-                let oldright = right.replace(Element::Single(0));
-                match oldright {
-                    Element::Pair(a, b) => {
-                        let leftopt = left_num.borrow();
-                        if let Some(leftelem) = &*leftopt {
-                            let new = a.borrow().add_singles(&leftelem.borrow());
-                            leftelem.replace(new);
-                        }
-                        if let Element::Single(rn) = *b.borrow() {
-                            return TResult::AddRight(rn);
-                        }
-                    },
-                    other => panic!("unexpected element {other:?}"),
-                }
-            },
-        };
-    }
-    if handle_single.is_some() {
-        let val = handle_single.unwrap();
-        if let Some(inc) = right_num {
-            node.replace(Element::Single(val + inc));
-            return TResult::Reduced;
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Explode(a, b, after) => write!(f, "explode [{a},{b}]: {after}"),
+            Step::Split(v, after) => write!(f, "split {v}: {after}"),
         }
-        *(left_num.borrow_mut()) = Some(node.clone());
     }
-
-    TResult::NoChange
 }
 
-fn traverse_split(node: &Node) -> TResult {
-    let mut traverse_left: Option<Node> = None;
-    let mut traverse_right: Option<Node> = None;
-    let mut handle_single: Option<u8> = None;
-    match &*node.borrow() {
-        Element::Pair(left, right) => {
-            traverse_left = Some(left.clone());
-            traverse_right = Some(right.clone());
-        },
-        Element::Single(val) => {
-            handle_single = Some(*val);
-        },
-    }
-    if traverse_left.is_some() {
-        let left = traverse_left.unwrap();
-        let right = traverse_right.unwrap();
-        match traverse_split(&left) {
-            TResult::Reduced => { return TResult::Reduced; },
-            TResult::NoChange => {},
-            _ => panic!(),
+impl SFNum {
+    fn fmt_at(&self, f: &mut fmt::Formatter<'_>, i: usize, depth: u8) -> Result<usize, fmt::Error> {
+        if self.0[i].depth == depth {
+            write!(f, "{}", self.0[i].val)?;
+            return Ok(i + 1);
         }
-        match traverse_split(&right) {
-            TResult::Reduced => { return TResult::Reduced; },
-            TResult::NoChange => {},
-            _ => panic!(),
+        write!(f, "[")?;
+        let mid = self.fmt_at(f, i, depth + 1)?;
+        write!(f, ",")?;
+        let end = self.fmt_at(f, mid, depth + 1)?;
+        write!(f, "]")?;
+        Ok(end)
+    }
+
+    fn magnitude_at(&self, i: usize, depth: u8) -> (u64, usize) {
+        if self.0[i].depth == depth {
+            return (self.0[i].val, i + 1);
         }
+        let (a, mid) = self.magnitude_at(i, depth + 1);
+        let (b, end) = self.magnitude_at(mid, depth + 1);
+        (a * 3 + b * 2, end)
     }
-    if handle_single.is_some() {
-        let val = handle_single.unwrap();
-        if val > 9 {
-            let newelem = Element::Pair(
-                Node::new(Element::Single(val / 2)),
-                Node::new(Element::Single((val + 1) / 2)),
-            );
-            node.replace(newelem);
-            return TResult::Reduced;
-        };
+
+    /// Explodes the leftmost pair of regular numbers nested inside four
+    /// pairs. The first two adjacent leaves of equal depth are always a pair.
+    fn explode(&mut self) -> Option<(u64, u64)> {
+        let leaves = &mut self.0;
+        let i = (0..leaves.len().saturating_sub(1))
+            .find(|&i| leaves[i].depth > 4 && leaves[i + 1].depth == leaves[i].depth)?;
+        let (a, b) = (leaves[i].val, leaves[i + 1].val);
+        if i > 0 {
+            leaves[i - 1].val += a;
+        }
+        if let Some(right) = leaves.get_mut(i + 2) {
+            right.val += b;
+        }
+        leaves[i] = Leaf { val: 0, depth: leaves[i].depth - 1 };
+        leaves.remove(i + 1);
+        Some((a, b))
     }
-    TResult::NoChange
-}
 
-fn magnitude(node: &Node) -> u64 {
-    match &*node.borrow() {
-        Element::Single(val) => *val as u64,
-        Element::Pair(a, b) => magnitude(a) * 3 + magnitude(b) * 2,
+    fn split(&mut self) -> Option<u64> {
+        let i = self.0.iter().position(|l| l.val > 9)?;
+        let Leaf { val, depth } = self.0[i];
+        self.0[i] = Leaf { val: val / 2, depth: depth + 1 };
+        self.0.insert(i + 1, Leaf { val: val.div_ceil(2), depth: depth + 1 });
+        Some(val)
     }
-}
 
-impl SFNum {
     fn reduce(&mut self) {
+        while self.explode().is_some() || self.split().is_some() {}
+    }
+
+    /// Reduces, recording every explode and split along the way.
+    fn reduce_traced(&mut self) -> Vec<Step> {
+        let mut trace = Vec::new();
         loop {
-            let ln = RefCell::new(None);
-            match traverse_explode(&self.0, 0, &ln, None) {
-                TResult::NoChange => { },
-                TResult::Reduced | TResult::AddRight(_) => {continue},
-                TResult::Explode => panic!(),
-            }
-            match traverse_split(&self.0) {
-                TResult::NoChange => { break; },
-                TResult::Reduced => {},
-                _ => panic!(),
+            if let Some((a, b)) = self.explode() {
+                trace.push(Step::Explode(a, b, self.clone()));
+            } else if let Some(v) = self.split() {
+                trace.push(Step::Split(v, self.clone()));
+            } else {
+                return trace;
             }
         }
     }
+
     fn magnitude(&self) -> u64 {
-        magnitude(&self.0)
+        self.magnitude_at(0, 0).0
+    }
+
+    /// The pair of `self` and `other`, not yet reduced.
+    fn join(&self, other: &Self) -> Self {
+        SFNum(self.0
+            .iter()
+            .chain(other.0.iter())
+            .map(|l| Leaf { val: l.val, depth: l.depth + 1 })
+            .collect())
     }
+
     fn add(&self, other: &Self) -> Self {
-        let mut new = SFNum(
-            Node::new(
-                Element::Pair(
-                    self.0.duplicate(),
-                    other.0.duplicate(),
-                )
-            )
-        );
+        let mut new = self.join(other);
         new.reduce();
         new
     }
@@ -269,7 +218,7 @@ impl SFNum {
 
 impl fmt::Display for SFNum {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        self.fmt_at(f, 0, 0).map(|_| ())
     }
 }
 
@@ -295,6 +244,19 @@ fn main() {
     let input: Vec<SFNum> = read_input();
     println!("Part 1: {}", part1(&input));
     println!("Part 2: {}", part2(&input));
+
+    // DAY18_TRACE=1 also shows every step of adding up the homework
+    if env::var_os("DAY18_TRACE").is_some() {
+        let mut sum = input[0].clone();
+        for row in input.iter().skip(1) {
+            println!("  {}\n+ {}", sum, row);
+            sum = sum.join(row);
+            for step in sum.reduce_traced() {
+                println!("    {}", step);
+            }
+            println!("= {}", sum);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -345,4 +307,68 @@ mod tests {
         assert_eq!(part1(&input), 4140);
         assert_eq!(part2(&input), 3993);
     }
+
+    #[test]
+    fn day18_flat_test() {
+        let leaf = |val, depth| Leaf { val, depth };
+        let sfnum = SFNum::from_str("[[1,2],[[3,4],5]]").unwrap();
+        assert_eq!(sfnum.0, vec![leaf(1, 2), leaf(2, 2), leaf(3, 3), leaf(4, 3), leaf(5, 2)]);
+        assert_eq!(sfnum.magnitude(), 143);
+
+        // a pair of pairs nested inside four pairs goes in steps
+        let mut sfnum = SFNum::from_str("[[[[[[1,2],[3,4]],6],7],8],9]").unwrap();
+        assert_eq!(sfnum.explode(), Some((1, 2)));
+        assert_eq!(format!("{sfnum}"), "[[[[[0,[5,4]],6],7],8],9]");
+        sfnum.reduce();
+        assert_eq!(format!("{sfnum}"), "[[[[5,0],[6,6]],8],9]");
+        let mut big = SFNum(vec![leaf(23, 1), leaf(1, 1)]);
+        big.reduce();
+        assert_eq!(format!("{big}"), "[[[5,6],[6,6]],1]");
+    }
+
+    #[test]
+    fn day18_parse_test() {
+        let sfnum = SFNum::from_str(" [ [10, 3] ,1 ] ").unwrap();
+        assert_eq!(format!("{sfnum}"), "[[10,3],1]");
+        assert_eq!(SFNum::from_str("[123,[4567,0]]").unwrap().magnitude(), 3 * 123 + 2 * (3 * 4567));
+        let err = |col, expected, found| Err(ParseError { col, expected, found });
+        assert_eq!(SFNum::from_str(""), err(1, "'[' or a number", None));
+        assert_eq!(SFNum::from_str("[1,2"), err(5, "']'", None));
+        assert_eq!(SFNum::from_str("[1,2]]"), err(6, "end of line", Some(']')));
+        assert_eq!(SFNum::from_str("[1, [2]]"), err(7, "','", Some(']')));
+        assert_eq!(SFNum::from_str("[1,2,3]"), err(5, "']'", Some(',')));
+        assert_eq!(SFNum::from_str("[1,x]"), err(4, "'[' or a number", Some('x')));
+        assert_eq!(SFNum::from_str("[1,99999999999]"), err(4, "a smaller number", Some('9')));
+        let nested = |n| format!("{}0{}", "[".repeat(n), ",0]".repeat(n));
+        let deep = SFNum::from_str(&nested(MAX_DEPTH as usize)).unwrap();
+        assert_eq!(deep.add(&deep).magnitude(), 0);
+        let n = MAX_DEPTH as usize + 1;
+        assert_eq!(SFNum::from_str(&nested(n)), err(n, "shallower nesting", Some('[')));
+        assert_eq!(
+            format!("{}", SFNum::from_str("[1 2]").unwrap_err()),
+            "column 4: expected ',', found '2'",
+        );
+
+        let mut sfnum = SFNum::from_str("[[[[[4,3],4],4],[7,[[8,4],9]]],[1,1]]").unwrap();
+        let trace: Vec<String> = sfnum.reduce_traced().iter().map(|s| s.to_string()).collect();
+        assert_eq!(trace, [
+            "explode [4,3]: [[[[0,7],4],[7,[[8,4],9]]],[1,1]]",
+            "explode [8,4]: [[[[0,7],4],[15,[0,13]]],[1,1]]",
+            "split 15: [[[[0,7],4],[[7,8],[0,13]]],[1,1]]",
+            "split 13: [[[[0,7],4],[[7,8],[0,[6,7]]]],[1,1]]",
+            "explode [6,7]: [[[[0,7],4],[[7,8],[6,0]]],[8,1]]",
+        ]);
+        assert!(sfnum.reduce_traced().is_empty());
+        let mut big = SFNum::from_str("[[10,3],1]").unwrap();
+        assert_eq!(big.reduce_traced().len(), 1);
+        assert_eq!(format!("{big}"), "[[[5,5],3],1]");
+
+        // explosions can push a leaf past the largest number parsed
+        let mut sfnum = SFNum::from_str("[[[[[1,1],4294967295],1],1],1]").unwrap();
+        assert_eq!(sfnum.explode(), Some((1, 1)));
+        assert_eq!(format!("{sfnum}"), "[[[[0,4294967296],1],1],1]");
+        assert_eq!(sfnum.split(), Some(1 << 32));
+        assert_eq!(sfnum.explode(), Some((1 << 31, 1 << 31)));
+        assert_eq!(SFNum::from_str("[1,4294967296]"), err(4, "a smaller number", Some('4')));
+    }
 }
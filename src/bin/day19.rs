@@ -147,7 +147,7 @@ fn setup(input: &[Vec<Input>]) -> Vec<Scanner> {
         .collect()
 }
 
-fn construct_space(scanners: &Vec<Scanner>) -> Vec<Scanner> {
+fn construct_space(scanners: &[Scanner]) -> Vec<Scanner> {
     let mut matched: HashMap<usize,Vec<usize>> = HashMap::new();
     for (s1, s2) in scanners
             .iter()
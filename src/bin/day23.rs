@@ -1,7 +1,7 @@
-use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::collections::{HashMap, HashSet};
 use std::iter;
 use std::vec::Vec;
+use advent2021::search::{dijkstra, Path};
 use ya_advent_lib::coords::Coord2D;
 use ya_advent_lib::grid::Grid;
 use ya_advent_lib::read::read_input;
@@ -133,7 +133,7 @@ fn make_map(input: &[String]) -> Map {
     let ppt = rooms[0].len();
     let idx_to_type: Vec<Pod> = Vec::from_iter(
         [Pod::A, Pod::B, Pod::C, Pod::D].iter()
-        .flat_map(|p| iter::repeat(*p).take(ppt))
+        .flat_map(|p| iter::repeat_n(*p, ppt))
     );
     Map {
         cells,
@@ -145,35 +145,14 @@ fn make_map(input: &[String]) -> Map {
     }
 }
 
+/// A set of pod positions, and what it cost to get there from the
+/// previous positions.
 #[derive(Clone, Eq, PartialEq)]
 struct State {
     cost: usize,
     pods: Vec<Coord2D>,
 }
 
-impl State {
-    fn pods_as_array(&self) -> [Coord2D; 16] {
-        let mut out = [Coord2D::new(0, 0); 16];
-        self.pods.iter().enumerate().for_each(|(idx, &c)| {
-            out[idx] = c;
-        });
-        out
-    }
-}
-
-impl Ord for State {
-    fn cmp(&self, other: &Self) -> Ordering {
-        other.cost.cmp(&self.cost)
-            .then_with(|| other.pods.cmp(&self.pods))
-    }
-}
-// `PartialOrd` needs to be implemented as well.
-impl PartialOrd for State {
-    fn partial_cmp(&self, other: &State) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
 fn get_initial_state(map: &Map) -> State {
     let mut a_pods: Vec<Coord2D> = Vec::new();
     let mut b_pods: Vec<Coord2D> = Vec::new();
@@ -227,15 +206,15 @@ struct StateExtra {
 }
 
 impl StateExtra {
-    fn new(state: &State) -> Self {
+    fn new(pods: &[Coord2D]) -> Self {
         let positions: HashMap<Coord2D, usize> = HashMap::from_iter(
-            state.pods.iter()
+            pods.iter()
             .enumerate()
             .map(|(idx,c)| (*c, idx))
         );
         Self {
-            cost: state.cost,
-            pods: state.pods.clone(),
+            cost: 0,
+            pods: pods.to_vec(),
             positions,
         }
     }
@@ -258,9 +237,7 @@ fn path_to(idx: usize, dest: Coord2D, state: &StateExtra, map: &Map) -> Option<S
             if state.positions.contains_key(&newpos) { continue; }
             match map.rules.get_c(newpos) {
                 Rule::Wall => { continue; },
-                Rule::Room(r) => {
-                    if r != map.idx_to_type[idx] && at_entry { continue; }
-                },
+                Rule::Room(r) if r != map.idx_to_type[idx] && at_entry => { continue; },
                 _ => {},
             }
             if !traversed.contains(&newpos) {
@@ -329,26 +306,17 @@ fn get_next_moves<'a>(state: &'a StateExtra, map: &'a Map) -> impl Iterator<Item
         })
 }
 
-fn search(map: &Map) -> usize {
-    let mut heap: BinaryHeap<State> = BinaryHeap::new();
-    let mut visited: HashMap<[Coord2D; 16], usize> = HashMap::new();
-    let initial_state = get_initial_state(map);
-    visited.insert(initial_state.pods_as_array(), 0);
-    heap.push(initial_state);
-    while let Some(state) = heap.pop() {
-        if is_final(&state.pods, map) {
-            return state.cost;
-        }
-        let stateextra = StateExtra::new(&state);
-        for next in get_next_moves(&stateextra, map) {
-            let key = next.pods_as_array();
-            if !visited.contains_key(&key) || visited[&key] > next.cost {
-                visited.insert(key, next.cost);
-                heap.push(next);
-            }
-        }
-    }
-    panic!("no solution found");
+fn search(map: &Map) -> Path<Vec<Coord2D>> {
+    dijkstra(
+        get_initial_state(map).pods,
+        |pods| is_final(pods, map),
+        |pods| {
+            let stateextra = StateExtra::new(pods);
+            get_next_moves(&stateextra, map)
+                .map(|next| (next.pods, next.cost))
+                .collect()
+        },
+    ).expect("no solution found")
 }
 
 fn part1(input: &[String]) -> usize {
@@ -358,12 +326,12 @@ fn part1(input: &[String]) -> usize {
     println!();
     map.rules.print();
     */
-    search(&map)
+    search(&map).cost
 }
 
 fn part2(input: &[String]) -> usize {
     let map = make_map(input);
-    search(&map)
+    search(&map).cost
 }
 
 fn modify_for_part2(input: &mut Vec<String>) {
@@ -390,4 +358,15 @@ mod tests {
         modify_for_part2(&mut input);
         assert_eq!(part2(&input), 44169);
     }
+
+    #[test]
+    fn day23_path_test() {
+        let input: Vec<String> = test_input(include_str!("day23.testinput"));
+        let map = make_map(&input);
+        let path = search(&map);
+        assert_eq!(path.nodes.first(), Some(&get_initial_state(&map).pods));
+        assert!(is_final(path.nodes.last().unwrap(), &map));
+        // every step moves exactly one pod
+        assert!(path.nodes.windows(2).all(|w| w[0].iter().zip(&w[1]).filter(|(a, b)| a != b).count() == 1));
+    }
 }
@@ -0,0 +1,222 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+use std::vec::Vec;
+
+/// A cheapest route, from the start node to the goal inclusive.
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct Path<N> {
+    pub cost: usize,
+    pub nodes: Vec<N>,
+}
+
+/// Every node reached so far, with its best known cost and the node it was
+/// reached from. The heap holds `(priority, cost, index)`; an entry whose cost
+/// is no longer the node's best is stale and skipped.
+struct Frontier<N> {
+    nodes: Vec<N>,
+    index: HashMap<N, usize>,
+    cost: Vec<usize>,
+    parent: Vec<Option<usize>>,
+    heap: BinaryHeap<Reverse<(usize, usize, usize)>>,
+}
+
+impl<N: Clone + Eq + Hash> Frontier<N> {
+    fn new(start: N, h: usize) -> Self {
+        let mut f = Self {
+            nodes: Vec::new(),
+            index: HashMap::new(),
+            cost: Vec::new(),
+            parent: Vec::new(),
+            heap: BinaryHeap::new(),
+        };
+        f.relax(start, 0, None, h);
+        f
+    }
+
+    /// Records `node` at `cost` if that beats what is known, returning its index.
+    fn relax(&mut self, node: N, cost: usize, parent: Option<usize>, h: usize) -> Option<usize> {
+        let idx = match self.index.get(&node) {
+            Some(&i) if self.cost[i] <= cost => return None,
+            Some(&i) => {
+                self.cost[i] = cost;
+                self.parent[i] = parent;
+                i
+            },
+            None => {
+                let i = self.nodes.len();
+                self.index.insert(node.clone(), i);
+                self.nodes.push(node);
+                self.cost.push(cost);
+                self.parent.push(parent);
+                i
+            },
+        };
+        self.heap.push(Reverse((cost + h, cost, idx)));
+        Some(idx)
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        while let Some(Reverse((_, cost, idx))) = self.heap.pop() {
+            if cost == self.cost[idx] {
+                return Some(idx);
+            }
+        }
+        None
+    }
+
+    fn peek_cost(&mut self) -> Option<usize> {
+        while let Some(&Reverse((_, cost, idx))) = self.heap.peek() {
+            if cost == self.cost[idx] {
+                return Some(cost);
+            }
+            self.heap.pop();
+        }
+        None
+    }
+
+    /// The nodes from the start to `idx`.
+    fn path(&self, mut idx: usize) -> Vec<N> {
+        let mut out = vec![self.nodes[idx].clone()];
+        while let Some(p) = self.parent[idx] {
+            out.push(self.nodes[p].clone());
+            idx = p;
+        }
+        out.reverse();
+        out
+    }
+}
+
+/// A* search from `start` to the first node passing `target_test`. The
+/// heuristic must never overestimate the remaining cost for the result to
+/// be a cheapest path.
+pub fn a_star<N, TFn, NFn, HFn>(
+    start: N,
+    target_test: TFn,
+    mut neighbors: NFn,
+    heuristic: HFn) -> Option<Path<N>>
+where N: Clone + Eq + Hash,
+      TFn: Fn(&N) -> bool,
+      NFn: FnMut(&N) -> Vec<(N, usize)>,
+      HFn: Fn(&N) -> usize,
+{
+    let mut frontier = Frontier::new(start.clone(), heuristic(&start));
+    while let Some(idx) = frontier.pop() {
+        let node = frontier.nodes[idx].clone();
+        if target_test(&node) {
+            return Some(Path { cost: frontier.cost[idx], nodes: frontier.path(idx) });
+        }
+        let cost = frontier.cost[idx];
+        for (next, step) in neighbors(&node) {
+            let h = heuristic(&next);
+            frontier.relax(next, cost + step, Some(idx), h);
+        }
+    }
+    None
+}
+
+pub fn dijkstra<N, TFn, NFn>(start: N, target_test: TFn, neighbors: NFn) -> Option<Path<N>>
+where N: Clone + Eq + Hash,
+      TFn: Fn(&N) -> bool,
+      NFn: FnMut(&N) -> Vec<(N, usize)>,
+{
+    a_star(start, target_test, neighbors, |_| 0)
+}
+
+type Edges<'a, N> = &'a mut dyn FnMut(&N) -> Vec<(N, usize)>;
+
+/// Dijkstra run from both ends at once, meeting in the middle. `reverse`
+/// gives the edges leading into a node; for an undirected graph it is the
+/// same as `neighbors`.
+pub fn bidirectional<N, NFn, RFn>(
+    start: N,
+    target: N,
+    mut neighbors: NFn,
+    mut reverse: RFn) -> Option<Path<N>>
+where N: Clone + Eq + Hash,
+      NFn: FnMut(&N) -> Vec<(N, usize)>,
+      RFn: FnMut(&N) -> Vec<(N, usize)>,
+{
+    let mut fwd = Frontier::new(start.clone(), 0);
+    let mut bwd = Frontier::new(target.clone(), 0);
+    // best meeting point so far: (total cost, node)
+    let mut best: Option<(usize, N)> = (start == target).then_some((0, start));
+    while let Some((f, b)) = fwd.peek_cost().zip(bwd.peek_cost()) {
+        if matches!(&best, Some((mu, _)) if f + b >= *mu) {
+            break;
+        }
+        let forward = fwd.heap.len() <= bwd.heap.len();
+        let (this, other, edges): (_, _, Edges<N>) = if forward {
+            (&mut fwd, &bwd, &mut neighbors)
+        } else {
+            (&mut bwd, &fwd, &mut reverse)
+        };
+        let idx = this.pop().unwrap();
+        let node = this.nodes[idx].clone();
+        let cost = this.cost[idx];
+        for (next, step) in edges(&node) {
+            if let Some(i) = this.relax(next, cost + step, Some(idx), 0) {
+                if let Some(&j) = other.index.get(&this.nodes[i]) {
+                    let total = this.cost[i] + other.cost[j];
+                    if best.as_ref().is_none_or(|(mu, _)| total < *mu) {
+                        best = Some((total, this.nodes[i].clone()));
+                    }
+                }
+            }
+        }
+    }
+    best.map(|(cost, meet)| {
+        let mut nodes = fwd.path(fwd.index[&meet]);
+        let mut back = bwd.path(bwd.index[&meet]);
+        back.pop();
+        back.reverse();
+        nodes.extend(back);
+        Path { cost, nodes }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // directed graph given as (from, to, cost)
+    const EDGES: [(u8, u8, usize); 9] = [
+        (0, 1, 7), (0, 2, 9), (0, 5, 14), (1, 2, 10), (1, 3, 15),
+        (2, 3, 11), (2, 5, 2), (3, 4, 6), (5, 4, 9),
+    ];
+
+    fn out(n: &u8) -> Vec<(u8, usize)> {
+        EDGES.iter().filter(|e| e.0 == *n).map(|e| (e.1, e.2)).collect()
+    }
+
+    fn into(n: &u8) -> Vec<(u8, usize)> {
+        EDGES.iter().filter(|e| e.1 == *n).map(|e| (e.0, e.2)).collect()
+    }
+
+    #[test]
+    fn search_test() {
+        let expect = Some(Path { cost: 20, nodes: vec![0, 2, 5, 4] });
+        assert_eq!(dijkstra(0, |n| *n == 4, out), expect);
+        assert_eq!(a_star(0, |n| *n == 4, out, |n| if *n == 4 { 0 } else { 6 }), expect);
+        assert_eq!(bidirectional(0, 4, out, into), expect);
+        assert_eq!(bidirectional(3, 3, out, into), Some(Path { cost: 0, nodes: vec![3] }));
+        assert_eq!(dijkstra(4, |n| *n == 0, out), None);
+        assert_eq!(bidirectional(4, 0, out, into), None);
+
+        // a grid with every edge costing 1: all methods agree on the cost
+        let grid = |&(x, y): &(i32, i32)| -> Vec<((i32, i32), usize)> {
+            [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)]
+                .into_iter()
+                .filter(|(x, y)| (0..20).contains(x) && (0..20).contains(y) && (*x != 10 || *y == 19))
+                .map(|p| (p, 1))
+                .collect()
+        };
+        let d = dijkstra((0, 0), |p| *p == (19, 0), grid).unwrap();
+        let a = a_star((0, 0), |p| *p == (19, 0), grid, |p| (19 - p.0 + p.1) as usize).unwrap();
+        let b = bidirectional((0, 0), (19, 0), grid, grid).unwrap();
+        assert_eq!(d.cost, 57);
+        assert_eq!((a.cost, b.cost), (57, 57));
+        assert_eq!(b.nodes.len(), 58);
+        assert!(b.nodes.windows(2).all(|w| grid(&w[0]).iter().any(|(n, _)| *n == w[1])));
+    }
+}